@@ -0,0 +1,73 @@
+//! Compares running programs with the plain interpreter, the decode cache
+//! and compiled code. Run with `cargo bench -p computer`.
+
+use std::time::{Duration, Instant};
+use computer::{assemble, Computer, ExecutionState, QueueIo};
+
+/// Sums the squares of the numbers from `count` down to 1, which keeps the
+/// computer in a tight loop of arithmetic and jumps.
+fn sum_of_squares_program(count: i64) -> Vec<i64> {
+    let mut program = assemble("
+        loop:   mul [n], [n], [square]
+                add [total], [square], [total]
+                add [n], #-1, [n]
+                jt [n], #loop
+                out [total]
+                hlt
+        n:      db 0
+        square: db 0
+        total:  db 0
+    ").unwrap();
+
+    let n = program.len() - 3;
+    program[n] = count;
+    program
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Engine {
+    Interpreter,
+    DecodeCache,
+    Compiled,
+}
+
+/// Runs a fresh computer on `program` `runs` times and returns the total
+/// time taken.
+fn time_runs(program: &[i64], runs: usize, engine: Engine) -> Duration {
+    let start = Instant::now();
+    for _ in 0..runs {
+        let mut computer = Computer::load_program(program.to_vec(), QueueIo::new());
+        match engine {
+            Engine::Interpreter => {},
+            Engine::DecodeCache => computer.enable_decode_cache(),
+            Engine::Compiled => computer.compile(),
+        }
+        assert_eq!(computer.run(), ExecutionState::Halted(program[0]));
+    }
+    start.elapsed()
+}
+
+fn compare(name: &str, program: &[i64], runs: usize) {
+    // Warm up, so the first measurement is not penalised.
+    time_runs(program, runs, Engine::Interpreter);
+
+    let interpreted = time_runs(program, runs, Engine::Interpreter);
+    println!("{}", name);
+    for &engine in &[Engine::Interpreter, Engine::DecodeCache, Engine::Compiled] {
+        let elapsed = time_runs(program, runs, engine);
+        println!("    {:<12} {:>10.2?}  speedup {:.2}x",
+            format!("{:?}", engine),
+            elapsed,
+            interpreted.as_secs_f64() / elapsed.as_secs_f64()
+        );
+    }
+}
+
+fn main() {
+    // One long run, where every instruction is decoded many times.
+    compare("1 run of 1,000,000 loops", &sum_of_squares_program(1_000_000), 1);
+
+    // Many short runs, like a noun/verb or phase setting sweep, where each
+    // new computer has to fill its cache from scratch.
+    compare("10,000 runs of 100 loops", &sum_of_squares_program(100), 10_000);
+}
@@ -0,0 +1,293 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use crate::ParameterMode;
+
+/// The reasons that a line of assembly can be rejected.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum AssembleErrorKind {
+    /// The mnemonic is not one that we know.
+    UnknownMnemonic(String),
+    /// The instruction was given the wrong number of operands.
+    WrongOperandCount { expected: usize, found: usize },
+    /// The operand could not be parsed.
+    BadOperand(String),
+    /// The operand is written to by the instruction, so it cannot be Immediate.
+    ImmediateWrite(String),
+    /// The label is referred to but never defined.
+    UndefinedLabel(String),
+    /// The label is defined more than once.
+    DuplicateLabel(String),
+    /// The line starts with an address (as in a disassembly listing) which
+    /// does not match the address it is actually assembled at.
+    AddressMismatch { expected: usize, found: usize },
+}
+
+/// An error from `assemble`, along with the (1-based) line it occurred on.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct AssembleError {
+    pub line: usize,
+    pub kind: AssembleErrorKind,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Line {}: ", self.line)?;
+        match &self.kind {
+            AssembleErrorKind::UnknownMnemonic(m) => write!(f, "unknown mnemonic '{}'", m),
+            AssembleErrorKind::WrongOperandCount { expected, found } =>
+                write!(f, "expected {} operands, found {}", expected, found),
+            AssembleErrorKind::BadOperand(op) => write!(f, "cannot parse operand '{}'", op),
+            AssembleErrorKind::ImmediateWrite(op) =>
+                write!(f, "operand '{}' is written to, so cannot be Immediate", op),
+            AssembleErrorKind::UndefinedLabel(label) => write!(f, "label '{}' is not defined", label),
+            AssembleErrorKind::DuplicateLabel(label) => write!(f, "label '{}' is already defined", label),
+            AssembleErrorKind::AddressMismatch { expected, found } =>
+                write!(f, "line is marked as address {} but is at address {}", expected, found),
+        }
+    }
+}
+
+impl Error for AssembleError {}
+
+/// The result of assembling a program: the program itself, plus the
+/// address of every label that was defined.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Assembly {
+    pub program: Vec<i64>,
+    pub labels: BTreeMap<String, usize>,
+}
+
+/// Assembles a program written in Intcode assembly.
+///
+/// Each line contains an optional label definition (`loop:`), then an
+/// instruction or data, then an optional comment starting with `;`. The
+/// instructions are `add`, `mul`, `in`, `out`, `jt`, `jf`, `lt`, `eq`, `arb`
+/// and `hlt`, and `db` (or `data`) emits its operands as raw words.
+/// Mnemonics are case insensitive.
+///
+/// Operands are numbers or labels, with a prefix for their mode: `#5` is
+/// Immediate, `rb+3` or `rb-3` is Relative, and `10` or `[10]` is Position.
+///
+/// The listings produced by `disassemble` are also accepted; the address
+/// at the start of each line is checked against where it is assembled.
+pub fn assemble(source: &str) -> Result<Vec<i64>, AssembleError> {
+    assemble_with_labels(source).map(|assembly| assembly.program)
+}
+
+/// As `assemble`, but also returns the addresses of the labels.
+pub fn assemble_with_labels(source: &str) -> Result<Assembly, AssembleError> {
+    // First pass: work out where everything goes, so that labels can be
+    // referred to before they are defined.
+    let mut statements = Vec::new();
+    let mut labels = BTreeMap::new();
+    let mut address = 0;
+
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let err = |kind| AssembleError { line, kind };
+
+        let mut text = text.split(';').next().unwrap_or("").trim();
+
+        while let Some((label, rest)) = split_label(text) {
+            if let Ok(expected) = label.parse::<usize>() {
+                if expected != address {
+                    return Err(err(AssembleErrorKind::AddressMismatch { expected, found: address }));
+                }
+            } else if labels.insert(label.to_string(), address).is_some() {
+                return Err(err(AssembleErrorKind::DuplicateLabel(label.to_string())));
+            }
+            text = rest;
+        }
+
+        if text.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, operands) = match text.find(char::is_whitespace) {
+            Some(n) => (&text[..n], text[n..].trim()),
+            None => (text, ""),
+        };
+
+        let operands = if operands.is_empty() {
+            Vec::new()
+        } else {
+            operands.split(',').map(|op| op.trim().to_string()).collect::<Vec<_>>()
+        };
+
+        let mnemonic = mnemonic.to_lowercase();
+        let size = match mnemonic.as_str() {
+            "db" | "data" => operands.len(),
+            _ => {
+                let (_, arity, _) = opcode_for(&mnemonic)
+                    .ok_or_else(|| err(AssembleErrorKind::UnknownMnemonic(mnemonic.clone())))?;
+                if operands.len() != arity {
+                    return Err(err(AssembleErrorKind::WrongOperandCount { expected: arity, found: operands.len() }));
+                }
+                arity + 1
+            }
+        };
+
+        address += size;
+        statements.push((line, mnemonic, operands));
+    }
+
+    // Second pass: encode everything, now that all labels are known.
+    let mut program = Vec::with_capacity(address);
+    for (line, mnemonic, operands) in statements {
+        let err = |kind| AssembleError { line, kind };
+
+        if mnemonic == "db" || mnemonic == "data" {
+            for op in &operands {
+                program.push(parse_value(op, &labels).map_err(err)?);
+            }
+            continue;
+        }
+
+        let (opcode, _, written_parameter) = opcode_for(&mnemonic).expect("Mnemonic checked in first pass");
+        let mut word = opcode;
+        let mut values = Vec::new();
+        for (i, op) in operands.iter().enumerate() {
+            let (mode, value) = parse_operand(op, &labels).map_err(err)?;
+            if mode == ParameterMode::Immediate && written_parameter == Some(i) {
+                return Err(err(AssembleErrorKind::ImmediateWrite(op.clone())));
+            }
+            word += (mode as i64) * 10_i64.pow(i as u32 + 2);
+            values.push(value);
+        }
+
+        program.push(word);
+        program.extend(values);
+    }
+
+    Ok(Assembly { program, labels })
+}
+
+/// Returns the opcode, number of parameters and index of the parameter
+/// that is written to (if any) for a mnemonic.
+fn opcode_for(mnemonic: &str) -> Option<(i64, usize, Option<usize>)> {
+    let info = match mnemonic {
+        "add" => (1, 3, Some(2)),
+        "mul" => (2, 3, Some(2)),
+        "in" => (3, 1, Some(0)),
+        "out" => (4, 1, None),
+        "jt" => (5, 2, None),
+        "jf" => (6, 2, None),
+        "lt" => (7, 3, Some(2)),
+        "eq" => (8, 3, Some(2)),
+        "arb" => (9, 1, None),
+        "hlt" => (99, 0, None),
+        _ => return None,
+    };
+
+    Some(info)
+}
+
+/// If the text starts with `name:`, returns the name and the remaining text.
+fn split_label(text: &str) -> Option<(&str, &str)> {
+    let colon = text.find(':')?;
+    let label = text[..colon].trim();
+    let is_name = !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if is_name {
+        Some((label, text[colon + 1..].trim()))
+    } else {
+        None
+    }
+}
+
+fn parse_operand(op: &str, labels: &BTreeMap<String, usize>) -> Result<(ParameterMode, i64), AssembleErrorKind> {
+    if let Some(rest) = op.strip_prefix('#') {
+        Ok((ParameterMode::Immediate, parse_value(rest, labels)?))
+    } else if let Some(rest) = op.strip_prefix("rb+") {
+        Ok((ParameterMode::Relative, parse_value(rest, labels)?))
+    } else if let Some(rest) = op.strip_prefix("rb-") {
+        let offset = parse_value(rest, labels)?.checked_neg()
+            .ok_or_else(|| AssembleErrorKind::BadOperand(op.to_string()))?;
+        Ok((ParameterMode::Relative, offset))
+    } else if let Some(rest) = op.strip_prefix('[').and_then(|r| r.strip_suffix(']')) {
+        Ok((ParameterMode::Position, parse_value(rest, labels)?))
+    } else {
+        Ok((ParameterMode::Position, parse_value(op, labels)?))
+    }
+}
+
+/// Parses a number or a label reference.
+fn parse_value(text: &str, labels: &BTreeMap<String, usize>) -> Result<i64, AssembleErrorKind> {
+    let text = text.trim();
+    if let Ok(value) = text.parse::<i64>() {
+        return Ok(value);
+    }
+
+    let is_label = text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !is_label {
+        return Err(AssembleErrorKind::BadOperand(text.to_string()));
+    }
+
+    labels.get(text)
+        .map(|&address| address as i64)
+        .ok_or_else(|| AssembleErrorKind::UndefinedLabel(text.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{disassemble, Computer, ExecutionState, QueueIo};
+
+    #[test]
+    pub fn assembles_all_modes() {
+        assert_eq!(assemble("add [10], #5, rb+3").unwrap(), vec![21001, 10, 5, 3]);
+        assert_eq!(assemble("out rb-7\nhlt").unwrap(), vec![204, -7, 99]);
+        assert_eq!(assemble("MUL 1, 2, 3").unwrap(), vec![2, 1, 2, 3]);
+    }
+
+    #[test]
+    pub fn assembles_labels_and_data() {
+        let source = "
+            ; Counts down from 3, outputting each number.
+            start:  out [counter]
+                    add [counter], #-1, [counter]
+                    jt [counter], #start
+                    hlt
+            counter: db 3
+        ";
+        let assembly = assemble_with_labels(source).unwrap();
+        assert_eq!(assembly.program, vec![4, 10, 1001, 10, -1, 10, 1005, 10, 0, 99, 3]);
+        assert_eq!(assembly.labels["start"], 0);
+        assert_eq!(assembly.labels["counter"], 10);
+
+        let mut computer = Computer::load_program(assembly.program, QueueIo::new());
+        assert_eq!(computer.run(), ExecutionState::Halted(4));
+        assert_eq!(computer.io_system.output(), &[3, 2, 1]);
+    }
+
+    #[test]
+    pub fn reports_errors_with_line_numbers() {
+        let err = |source| assemble(source).unwrap_err();
+        assert_eq!(err("hlt\nfoo 1"), AssembleError { line: 2, kind: AssembleErrorKind::UnknownMnemonic("foo".into()) });
+        assert_eq!(err("add 1, 2").kind, AssembleErrorKind::WrongOperandCount { expected: 3, found: 2 });
+        assert_eq!(err("out #1x").kind, AssembleErrorKind::BadOperand("1x".into()));
+        assert_eq!(err("out rb--9223372036854775808").kind, AssembleErrorKind::BadOperand("rb--9223372036854775808".into()));
+        assert_eq!(err("in #1").kind, AssembleErrorKind::ImmediateWrite("#1".into()));
+        assert_eq!(err("jt #1, #nowhere").kind, AssembleErrorKind::UndefinedLabel("nowhere".into()));
+        assert_eq!(err("a: hlt\na: hlt").kind, AssembleErrorKind::DuplicateLabel("a".into()));
+        assert_eq!(err("0: hlt\n2: hlt").kind, AssembleErrorKind::AddressMismatch { expected: 2, found: 1 });
+        assert_eq!(err("add 1, 2").to_string(), "Line 1: expected 3 operands, found 2");
+    }
+
+    #[test]
+    pub fn round_trips_with_the_disassembler() {
+        let programs = vec![
+            vec![109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99],
+            vec![3, 225, 1, 225, 6, 6, 1100, 1, 238, 225, 104, 0, 1105, 1, 4, 677, 0],
+        ];
+
+        for program in programs {
+            let listing = disassemble(&program).iter()
+                .map(|line| line.to_string())
+                .collect::<Vec<_>>()
+                .join("\n");
+            assert_eq!(assemble(&listing).unwrap(), program, "Listing was:\n{}", listing);
+        }
+    }
+}
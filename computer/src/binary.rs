@@ -0,0 +1,47 @@
+use std::io::{self, Read, Write};
+#[cfg(test)]
+use std::path::Path;
+
+pub(crate) fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub(crate) fn write_u64<W: Write>(writer: &mut W, value: u64) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+pub(crate) fn write_i64<W: Write>(writer: &mut W, value: i64) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+pub(crate) fn read_u8<R: Read>(reader: &mut R) -> io::Result<u8> {
+    let mut buf = [0; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+pub(crate) fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+pub(crate) fn read_i64<R: Read>(reader: &mut R) -> io::Result<i64> {
+    let mut buf = [0; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(i64::from_le_bytes(buf))
+}
+
+/// Saves something to a file in the temporary directory with `save`, then
+/// loads it back with `load`. The file is removed before returning.
+#[cfg(test)]
+pub(crate) fn through_temp_file<T, S, L>(name: &str, save: S, load: L) -> io::Result<T>
+    where S: FnOnce(&Path) -> io::Result<()>,
+          L: FnOnce(&Path) -> io::Result<T>
+{
+    let path = std::env::temp_dir().join(format!("computer-{}-test-{}.bin", name, std::process::id()));
+    save(&path)?;
+    let loaded = load(&path);
+    std::fs::remove_file(&path)?;
+    loaded
+}
@@ -0,0 +1,363 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use crate::disassembler::{disassemble_with_starts, find_reachable_instructions, static_jump_target};
+use crate::{Image, Instruction, Line, ParameterMode};
+
+/// Where execution can go after the last instruction of a basic block.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Successor {
+    /// Execution carries on with the block that starts straight after this
+    /// one, either because this block does not end in a jump or because the
+    /// jump might not be taken.
+    FallThrough(usize),
+    /// A jump to the block at an Immediate mode target.
+    Jump(usize),
+    /// A jump whose target is read from memory, so it cannot be known
+    /// without running the program.
+    IndirectJump,
+    /// Execution goes to an address that does not hold an instruction, so
+    /// the computer faults there unless the program writes one first. The
+    /// address may be negative.
+    Invalid(i64),
+}
+
+/// A run of instructions which is only ever entered at the top and left at
+/// the bottom.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BasicBlock {
+    /// The address of the first instruction.
+    pub start: usize,
+    /// The address just past the last instruction.
+    pub end: usize,
+    /// The instructions of the block, which are all `Line::Instruction`s.
+    pub lines: Vec<Line>,
+    pub successors: Vec<Successor>,
+    /// Whether the block can be reached by following the flow of control
+    /// from the entry point, including jumps with Immediate mode targets.
+    pub reachable: bool,
+}
+
+impl BasicBlock {
+    /// Returns true if the block ends in a jump whose target is unknown.
+    pub fn has_indirect_jump(&self) -> bool {
+        self.successors.contains(&Successor::IndirectJump)
+    }
+}
+
+/// The control flow graph of a program, made of the basic blocks of every
+/// instruction that `disassemble` would list.
+///
+/// Blocks that are not reachable from the entry point are kept, since they
+/// may be the targets of indirect jumps or code the program patches at
+/// runtime, but if nothing jumps to them they are probably data which
+/// happens to decode as instructions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ControlFlowGraph {
+    /// The blocks, keyed by their start address.
+    blocks: BTreeMap<usize, BasicBlock>,
+    entry_point: usize,
+    /// The labels from the image's symbols, keyed by address.
+    labels: BTreeMap<usize, String>,
+}
+
+impl ControlFlowGraph {
+    pub fn new(image: &Image) -> Self {
+        let program = &image.program;
+        let entry_point = image.entry_point.unwrap_or(0);
+        let reachable = find_reachable_instructions(program, entry_point);
+
+        let instructions = disassemble_with_starts(program, &[entry_point]).into_iter()
+            .filter(|line| matches!(line, Line::Instruction { .. }))
+            .collect::<Vec<_>>();
+        let jump_targets = instructions.iter()
+            .filter_map(Line::jump_target)
+            .collect::<BTreeSet<_>>();
+
+        // Group the instructions into blocks. A block starts at the entry
+        // point, at jump targets, after jumps and halts, and after data.
+        let mut groups: Vec<Vec<Line>> = Vec::new();
+        let mut previous_end = None;
+        let mut previous_ends_block = true;
+        for line in instructions {
+            let address = line.address();
+            let starts_block = previous_ends_block
+                || previous_end != Some(address)
+                || address == entry_point
+                || jump_targets.contains(&address);
+
+            previous_end = Some(address + line.word_count());
+            previous_ends_block = match &line {
+                Line::Instruction { instruction, .. } => ends_block(*instruction),
+                Line::Data { .. } => true,
+            };
+
+            match groups.last_mut() {
+                Some(group) if !starts_block => group.push(line),
+                _ => groups.push(vec![line]),
+            }
+        }
+
+        let starts = groups.iter().map(|group| group[0].address()).collect::<BTreeSet<_>>();
+        let blocks = groups.into_iter()
+            .map(|lines| {
+                let start = lines[0].address();
+                let last = lines.last().expect("Blocks are never empty");
+                let end = last.address() + last.word_count();
+                let successors = successors(last, &starts);
+                let block = BasicBlock { start, end, lines, successors, reachable: reachable.contains(&start) };
+                (start, block)
+            })
+            .collect();
+
+        let labels = image.symbols.iter()
+            .map(|(name, &address)| (address, name.clone()))
+            .collect();
+
+        Self { blocks, entry_point, labels }
+    }
+
+    /// Returns the blocks in address order.
+    pub fn blocks(&self) -> impl Iterator<Item = &BasicBlock> {
+        self.blocks.values()
+    }
+
+    /// Returns the block that starts at `address`.
+    pub fn block(&self, address: usize) -> Option<&BasicBlock> {
+        self.blocks.get(&address)
+    }
+
+    /// Returns the block that the word at `address` is part of.
+    pub fn block_containing(&self, address: usize) -> Option<&BasicBlock> {
+        self.blocks.range(..=address).next_back()
+            .map(|(_, block)| block)
+            .filter(|block| address < block.end)
+    }
+
+    /// Returns the blocks that end in a jump whose target is unknown.
+    pub fn indirect_jumps(&self) -> impl Iterator<Item = &BasicBlock> {
+        self.blocks().filter(|block| block.has_indirect_jump())
+    }
+
+    /// Returns the blocks that cannot be reached from the entry point
+    /// without an indirect jump.
+    pub fn unreachable_blocks(&self) -> impl Iterator<Item = &BasicBlock> {
+        self.blocks().filter(|block| !block.reachable)
+    }
+
+    /// Renders the graph in Graphviz's DOT language. Blocks that cannot be
+    /// reached are dashed and grey, jumps are labelled with their condition
+    /// and unknown or invalid destinations get nodes of their own.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        self.write_dot(&mut dot).expect("Writing to a String cannot fail");
+        dot
+    }
+
+    fn write_dot(&self, dot: &mut String) -> std::fmt::Result {
+        writeln!(dot, "digraph program {{")?;
+        writeln!(dot, "    node [shape=box, fontname=\"monospace\"];")?;
+        writeln!(dot, "    entry [shape=point];")?;
+
+        let mut invalid = BTreeSet::new();
+        if self.blocks.contains_key(&self.entry_point) {
+            writeln!(dot, "    entry -> b{};", self.entry_point)?;
+        } else {
+            invalid.insert(self.entry_point as i64);
+            writeln!(dot, "    entry -> \"invalid{}\";", self.entry_point)?;
+        }
+
+        for block in self.blocks() {
+            let mut label = String::new();
+            if let Some(name) = self.labels.get(&block.start) {
+                label += &format!("{}:\\l", escape(name));
+            }
+            for line in &block.lines {
+                label += &format!("{}\\l", escape(&line.to_string()));
+            }
+            let style = if block.reachable { "" } else { ", style=dashed, color=grey, fontcolor=grey" };
+            writeln!(dot, "    b{} [label=\"{}\"{}];", block.start, label, style)?;
+
+            let condition = match block.lines.last() {
+                Some(Line::Instruction { instruction: Instruction::JumpIfTrue(..), .. }) => "true",
+                Some(Line::Instruction { instruction: Instruction::JumpIfFalse(..), .. }) => "false",
+                _ => "",
+            };
+            for successor in &block.successors {
+                match *successor {
+                    Successor::FallThrough(target) => writeln!(dot, "    b{} -> b{};", block.start, target)?,
+                    Successor::Jump(target) => {
+                        writeln!(dot, "    b{} -> b{} [label=\"{}\"];", block.start, target, condition)?
+                    },
+                    Successor::IndirectJump => {
+                        writeln!(dot, "    indirect{} [shape=diamond, label=\"?\"];", block.start)?;
+                        writeln!(dot, "    b{} -> indirect{} [label=\"{}\", style=dashed];", block.start, block.start, condition)?;
+                    },
+                    Successor::Invalid(target) => {
+                        invalid.insert(target);
+                        writeln!(dot, "    b{} -> \"invalid{}\" [color=red];", block.start, target)?;
+                    },
+                }
+            }
+        }
+
+        for address in invalid {
+            writeln!(dot, "    \"invalid{}\" [shape=octagon, color=red, label=\"{}: invalid\"];", address, address)?;
+        }
+        writeln!(dot, "}}")
+    }
+}
+
+/// Returns true if nothing can follow the instruction in the same block.
+fn ends_block(instruction: Instruction) -> bool {
+    matches!(instruction, Instruction::JumpIfTrue(..) | Instruction::JumpIfFalse(..) | Instruction::Halt)
+}
+
+/// Returns where execution can go after `last`, the last line of a block.
+fn successors(last: &Line, starts: &BTreeSet<usize>) -> Vec<Successor> {
+    let (address, instruction, operands) = match last {
+        Line::Instruction { address, instruction, operands } => (*address, *instruction, operands),
+        Line::Data { .. } => return Vec::new(),
+    };
+
+    let next = address + operands.len() + 1;
+    let to = |target: usize, successor: fn(usize) -> Successor| {
+        if starts.contains(&target) {
+            successor(target)
+        } else {
+            Successor::Invalid(target as i64)
+        }
+    };
+
+    // A jump whose condition is an Immediate mode parameter only goes one way.
+    let (can_jump, can_fall_through) = match instruction {
+        Instruction::JumpIfTrue(ParameterMode::Immediate, _) => (operands[0] != 0, operands[0] == 0),
+        Instruction::JumpIfFalse(ParameterMode::Immediate, _) => (operands[0] == 0, operands[0] != 0),
+        Instruction::JumpIfTrue(..) | Instruction::JumpIfFalse(..) => (true, true),
+        Instruction::Halt => (false, false),
+        _ => (false, true),
+    };
+
+    let mut successors = Vec::new();
+    if can_jump {
+        successors.push(match static_jump_target(instruction, operands) {
+            Some(target) => to(target, Successor::Jump),
+            None if operands[1] < 0 && instruction.parameter_modes()[1] == ParameterMode::Immediate => {
+                Successor::Invalid(operands[1])
+            },
+            None => Successor::IndirectJump,
+        });
+    }
+    if can_fall_through {
+        successors.push(to(next, Successor::FallThrough));
+    }
+    successors
+}
+
+/// Escapes a string for use inside a quoted DOT label.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble_with_labels;
+
+    fn graph(source: &str) -> ControlFlowGraph {
+        ControlFlowGraph::new(&Image::from(assemble_with_labels(source).unwrap()))
+    }
+
+    fn starts(graph: &ControlFlowGraph) -> Vec<usize> {
+        graph.blocks().map(|block| block.start).collect()
+    }
+
+    #[test]
+    pub fn blocks_split_at_jump_targets_and_after_jumps() {
+        let graph = graph("
+                    in [n]
+            loop:   out [n]
+                    add [n], #-1, [n]
+                    jt [n], #loop
+                    hlt
+            n:      db 0
+        ");
+
+        assert_eq!(starts(&graph), vec![0, 2, 11]);
+        assert_eq!(graph.block(0).unwrap().successors, vec![Successor::FallThrough(2)]);
+        assert_eq!(graph.block(2).unwrap().successors, vec![Successor::Jump(2), Successor::FallThrough(11)]);
+        assert_eq!(graph.block(2).unwrap().end, 11);
+        assert_eq!(graph.block(11).unwrap().successors, vec![]);
+        assert_eq!(graph.block_containing(6).unwrap().start, 2);
+        assert!(graph.block_containing(12).is_none());
+        assert!(graph.blocks().all(|block| block.reachable));
+    }
+
+    #[test]
+    pub fn constant_conditions_go_one_way() {
+        let graph = graph("
+                    jt #1, #skip
+                    out #1
+            skip:   jf #1, #0
+                    hlt
+        ");
+
+        assert_eq!(graph.block(0).unwrap().successors, vec![Successor::Jump(5)]);
+        assert_eq!(graph.block(5).unwrap().successors, vec![Successor::FallThrough(8)]);
+        assert!(!graph.block(3).unwrap().reachable);
+        assert_eq!(graph.unreachable_blocks().map(|block| block.start).collect::<Vec<_>>(), vec![3]);
+    }
+
+    #[test]
+    pub fn indirect_jumps_are_marked() {
+        let graph = graph("
+                    add #ret, #0, rb+0
+                    jt #1, #double
+            ret:    out [value]
+                    hlt
+            double: mul [value], #2, [value]
+                    jt #1, rb+0
+            value:  db 3
+        ");
+
+        let indirect = graph.indirect_jumps().map(|block| block.start).collect::<Vec<_>>();
+        assert_eq!(indirect, vec![10]);
+        assert_eq!(graph.block(10).unwrap().successors, vec![Successor::IndirectJump]);
+        // Only reached by returning from `double`.
+        assert!(!graph.block(7).unwrap().reachable);
+    }
+
+    #[test]
+    pub fn jumps_to_data_are_invalid() {
+        // Day 5's program patches the instruction at address 6 before
+        // running it, so statically it is not an instruction at all.
+        let graph = ControlFlowGraph::new(&Image::from(vec![3, 225, 1, 225, 6, 6, 1100, 1, 238, 225, 104, 0]));
+        assert_eq!(graph.block(0).unwrap().successors, vec![Successor::Invalid(6)]);
+
+        let graph = ControlFlowGraph::new(&Image::from(vec![1105, 1, 7, 1105, 1, -1, 99]));
+        assert_eq!(graph.block(0).unwrap().successors, vec![Successor::Invalid(7)]);
+        assert_eq!(graph.block(3).unwrap().successors, vec![Successor::Invalid(-1)]);
+    }
+
+    #[test]
+    pub fn dot_output_has_a_node_per_block_and_an_edge_per_successor() {
+        let graph = graph("
+            start:  jt [flag], [target]
+                    hlt
+            flag:   db 1
+            target: db 0
+        ");
+        let dot = graph.to_dot();
+
+        assert!(dot.starts_with("digraph program {\n"));
+        assert!(dot.contains("    entry -> b0;\n"));
+        assert!(dot.contains("    b0 [label=\"start:\\l     0: JT [4], [5]\\l\"];\n"));
+        assert!(dot.contains("    b0 -> indirect0 [label=\"true\", style=dashed];\n"));
+        assert!(dot.contains("    b0 -> b3;\n"));
+        assert!(dot.contains("    b3 [label=\"     3: HLT\\l\"];\n"));
+        assert!(dot.ends_with("}\n"));
+
+        let dot = ControlFlowGraph::new(&Image::from(vec![104, 1, 1105, 1, 7, 104, 2, 99, 9])).to_dot();
+        assert!(dot.contains("    b5 [label=\"     5: OUT #2\\l\", style=dashed, color=grey, fontcolor=grey];\n"));
+        assert!(dot.contains("    b0 -> b7 [label=\"true\"];\n"));
+    }
+}
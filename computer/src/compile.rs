@@ -0,0 +1,671 @@
+use crate::{check_address, Computer, ComputerIo, ExecutionState, Fault, Instruction, Memory, OverflowPolicy, ParameterMode, Word};
+
+/// The most words that an instruction occupies.
+const MAX_INSTRUCTION_LEN: usize = 4;
+
+/// A compiled instruction. It updates the computer's registers, memory and
+/// execution state exactly as the interpreter would.
+type Op<I, W> = Box<dyn Fn(&mut Computer<I, W>) -> Result<Outcome<W>, Fault> + Send>;
+
+/// What a compiled instruction did, beyond updating the registers.
+enum Outcome<W> {
+    Continue,
+    /// The instruction stored a value at the address.
+    Wrote(usize),
+    /// The instruction was a Write of the value.
+    Output(W),
+}
+
+/// What is known about the code at an address.
+enum Slot<I, W> {
+    /// Execution has not reached the address yet.
+    Unknown,
+    /// The interpreter has to execute the instruction here, because it is
+    /// invalid, would certainly fault, or has been overwritten since it was
+    /// compiled.
+    Interpreted,
+    /// The instruction here has been compiled. `len` is the number of words
+    /// it occupies, all of which it depends on.
+    Compiled { op: Op<I, W>, len: usize },
+}
+
+/// A program's code, translated into a closure for each instruction. The
+/// closure for an instruction is specialised for its parameter modes, with
+/// the parameters themselves baked in, so that executing it does no
+/// decoding at all.
+///
+/// Because the parameters are baked in, any write to a word of a compiled
+/// instruction sends that instruction back to the interpreter.
+pub(crate) struct CompiledCode<I, W> {
+    slots: Vec<Slot<I, W>>,
+}
+
+impl<I, W> CompiledCode<I, W>
+    where I: ComputerIo<W>,
+          W: Word
+{
+    pub fn new() -> Self {
+        Self { slots: Vec::new() }
+    }
+
+    /// Returns the number of compiled instructions.
+    pub fn len(&self) -> usize {
+        self.slots.iter().filter(|slot| matches!(slot, Slot::Compiled { .. })).count()
+    }
+
+    /// Compiles everything reachable from `start` that has not been seen
+    /// before. Control flow is followed through jumps to Immediate mode
+    /// targets; the targets of other jumps are compiled when they are
+    /// first executed.
+    pub fn discover(&mut self, memory: &Memory<W>, start: usize) {
+        let mut pending = vec![start];
+
+        while let Some(ip) = pending.pop() {
+            if ip >= memory.len() || !matches!(self.slot(ip), Slot::Unknown) {
+                continue;
+            }
+
+            let inst = match Instruction::decode(memory[ip].clone()) {
+                Ok(inst) => inst,
+                Err(_) => {
+                    self.set_slot(ip, Slot::Interpreted);
+                    continue;
+                }
+            };
+
+            let len = inst.instruction_pointer_increment();
+            match inst {
+                Instruction::Halt => {},
+                Instruction::JumpIfTrue(_, target) | Instruction::JumpIfFalse(_, target) => {
+                    pending.push(ip + len);
+                    if target == ParameterMode::Immediate {
+                        if let Some(target) = memory[ip + 2].to_i64().filter(|&target| target >= 0) {
+                            pending.push(target as usize);
+                        }
+                    }
+                },
+                _ => pending.push(ip + len),
+            }
+
+            let slot = match compile_instruction(memory, ip, inst) {
+                Some(op) => Slot::Compiled { op, len },
+                None => Slot::Interpreted,
+            };
+            self.set_slot(ip, slot);
+        }
+    }
+
+    /// Executes compiled instructions until `max_instructions` have been
+    /// executed, one of them is a Write, the computer stops running, or the
+    /// next instruction has to be interpreted. Returns the number executed,
+    /// and either the value written or the address and fault of the
+    /// instruction that faulted.
+    pub fn run(&mut self, computer: &mut Computer<I, W>, max_instructions: usize) -> (usize, Result<Option<W>, (usize, Fault)>) {
+        let mut executed = 0;
+
+        while executed < max_instructions {
+            let ip = computer.instruction_pointer;
+            let result = match self.execute(computer) {
+                Some(result) => result,
+                None => break,
+            };
+
+            executed += 1;
+            match result {
+                Ok(None) if computer.execution_state == ExecutionState::Running => {},
+                Ok(output) => return (executed, Ok(output)),
+                Err(fault) => return (executed, Err((ip, fault))),
+            }
+        }
+
+        (executed, Ok(None))
+    }
+
+    /// Executes the compiled instruction at the computer's instruction
+    /// pointer, compiling it first if it has not been seen before. Returns
+    /// the value written if the instruction was a Write.
+    ///
+    /// Returns `None`, having done nothing, if the interpreter has to
+    /// execute the instruction instead.
+    fn execute(&mut self, computer: &mut Computer<I, W>) -> Option<Result<Option<W>, Fault>> {
+        let ip = computer.instruction_pointer;
+        if ip >= computer.memory.len() {
+            return None;
+        }
+
+        if let Slot::Unknown = self.slot(ip) {
+            self.discover(&computer.memory, ip);
+        }
+
+        let outcome = match self.slot(ip) {
+            Slot::Compiled { op, .. } => op(computer),
+            _ => return None,
+        };
+
+        let result = match outcome {
+            Ok(Outcome::Continue) => Ok(None),
+            Ok(Outcome::Wrote(address)) => {
+                self.invalidate(address);
+                if let Some(cache) = &mut computer.decode_cache {
+                    cache.invalidate(address);
+                }
+                Ok(None)
+            },
+            Ok(Outcome::Output(value)) => Ok(Some(value)),
+            Err(fault) => Err(fault),
+        };
+
+        Some(result)
+    }
+
+    /// Sends every compiled instruction that includes `address` back to
+    /// the interpreter. Called whenever memory is written.
+    pub fn invalidate(&mut self, address: usize) {
+        let first = address.saturating_sub(MAX_INSTRUCTION_LEN - 1);
+
+        for start in first..=address {
+            if let Some(slot) = self.slots.get_mut(start) {
+                if let Slot::Compiled { len, .. } = *slot {
+                    if start + len > address {
+                        *slot = Slot::Interpreted;
+                    }
+                }
+            }
+        }
+    }
+
+    fn slot(&self, address: usize) -> &Slot<I, W> {
+        self.slots.get(address).unwrap_or(&Slot::Unknown)
+    }
+
+    fn set_slot(&mut self, address: usize, slot: Slot<I, W>) {
+        if address >= self.slots.len() {
+            self.slots.resize_with(address + 1, || Slot::Unknown);
+        }
+        self.slots[address] = slot;
+    }
+}
+
+/// A parameter of an instruction, as found at compile time.
+enum Param<W> {
+    Immediate(W),
+    Position(usize),
+    Relative(i64),
+}
+
+/// Reads the parameter at `address`. Returns `None` if a Position mode
+/// parameter is not a usable address, so that the interpreter can raise
+/// the fault when the instruction is executed.
+fn param<W: Word>(memory: &Memory<W>, address: usize, mode: ParameterMode) -> Option<Param<W>> {
+    let word = memory.get(address);
+    match mode {
+        ParameterMode::Immediate => Some(Param::Immediate(word)),
+        ParameterMode::Position => check_address(memory, word.to_i64()?).ok().map(Param::Position),
+        ParameterMode::Relative => word.to_i64().map(Param::Relative),
+    }
+}
+
+/// A parameter that is read, with its mode fixed at compile time.
+trait Source<W>: Send + 'static {
+    fn read(&self, memory: &Memory<W>, relative_base: i64) -> Result<W, Fault>;
+}
+
+/// A parameter that is written to, with its mode fixed at compile time.
+trait Target: Send + 'static {
+    fn address<W: Word>(&self, memory: &Memory<W>, relative_base: i64) -> Result<usize, Fault>;
+}
+
+struct Immediate<W>(W);
+/// The address has already been checked.
+struct Position(usize);
+struct Relative(i64);
+
+impl<W: Word> Source<W> for Immediate<W> {
+    fn read(&self, _memory: &Memory<W>, _relative_base: i64) -> Result<W, Fault> {
+        Ok(self.0.clone())
+    }
+}
+
+impl<W: Word> Source<W> for Position {
+    fn read(&self, memory: &Memory<W>, _relative_base: i64) -> Result<W, Fault> {
+        Ok(memory[self.0].clone())
+    }
+}
+
+impl<W: Word> Source<W> for Relative {
+    fn read(&self, memory: &Memory<W>, relative_base: i64) -> Result<W, Fault> {
+        Ok(memory[self.address(memory, relative_base)?].clone())
+    }
+}
+
+impl Target for Position {
+    fn address<W: Word>(&self, _memory: &Memory<W>, _relative_base: i64) -> Result<usize, Fault> {
+        Ok(self.0)
+    }
+}
+
+impl Target for Relative {
+    fn address<W: Word>(&self, memory: &Memory<W>, relative_base: i64) -> Result<usize, Fault> {
+        let address = relative_base.checked_add(self.0).ok_or(Fault::ArithmeticOverflow)?;
+        check_address(memory, address)
+    }
+}
+
+/// Evaluates `$body` with `$name` bound to the `Source` for the parameter's
+/// mode, so that `$body` is instantiated separately for each mode.
+macro_rules! with_source {
+    ($param:expr, $name:ident => $body:expr) => {
+        match $param {
+            Param::Immediate(value) => { let $name = Immediate(value); $body },
+            Param::Position(address) => { let $name = Position(address); $body },
+            Param::Relative(offset) => { let $name = Relative(offset); $body },
+        }
+    };
+}
+
+/// As `with_source`, for a parameter that is written to. Decoding never
+/// allows these to be Immediate mode.
+macro_rules! with_target {
+    ($param:expr, $name:ident => $body:expr) => {
+        match $param {
+            Param::Immediate(_) => None,
+            Param::Position(address) => { let $name = Position(address); $body },
+            Param::Relative(offset) => { let $name = Relative(offset); $body },
+        }
+    };
+}
+
+/// Compiles the instruction at `ip`, which decoded as `inst`. Returns
+/// `None` if it has to be left to the interpreter.
+fn compile_instruction<I, W>(memory: &Memory<W>, ip: usize, inst: Instruction) -> Option<Op<I, W>>
+    where I: ComputerIo<W>,
+          W: Word
+{
+    let next = ip + inst.instruction_pointer_increment();
+    let param = |number: usize, mode| param(memory, ip + number, mode);
+
+    match inst {
+        Instruction::Add(m1, m2, m3) => {
+            let (p1, p2, p3) = (param(1, m1)?, param(2, m2)?, param(3, m3)?);
+            with_source!(p1, a => with_source!(p2, b => with_target!(p3, c =>
+                Some(store(a, b, c, next, |policy, x, y| policy.add(x, y)))
+            )))
+        },
+
+        Instruction::Multiply(m1, m2, m3) => {
+            let (p1, p2, p3) = (param(1, m1)?, param(2, m2)?, param(3, m3)?);
+            with_source!(p1, a => with_source!(p2, b => with_target!(p3, c =>
+                Some(store(a, b, c, next, |policy, x, y| policy.multiply(x, y)))
+            )))
+        },
+
+        Instruction::LessThan(m1, m2, m3) => {
+            let (p1, p2, p3) = (param(1, m1)?, param(2, m2)?, param(3, m3)?);
+            with_source!(p1, a => with_source!(p2, b => with_target!(p3, c =>
+                Some(store(a, b, c, next, |_, x, y| Ok(if x < y { W::one() } else { W::zero() })))
+            )))
+        },
+
+        Instruction::Equals(m1, m2, m3) => {
+            let (p1, p2, p3) = (param(1, m1)?, param(2, m2)?, param(3, m3)?);
+            with_source!(p1, a => with_source!(p2, b => with_target!(p3, c =>
+                Some(store(a, b, c, next, |_, x, y| Ok(if x == y { W::one() } else { W::zero() })))
+            )))
+        },
+
+        Instruction::Read(m1) => {
+            with_target!(param(1, m1)?, c => Some(read(c, next)))
+        },
+
+        Instruction::Write(m1) => {
+            with_source!(param(1, m1)?, a => Some(write(a, next)))
+        },
+
+        Instruction::JumpIfTrue(m1, m2) => {
+            let (p1, p2) = (param(1, m1)?, param(2, m2)?);
+            with_source!(p1, a => with_source!(p2, b =>
+                Some(jump(a, b, next, |x: &W| !x.is_zero()))
+            ))
+        },
+
+        Instruction::JumpIfFalse(m1, m2) => {
+            let (p1, p2) = (param(1, m1)?, param(2, m2)?);
+            with_source!(p1, a => with_source!(p2, b =>
+                Some(jump(a, b, next, |x: &W| x.is_zero()))
+            ))
+        },
+
+        Instruction::RelativeBaseOffset(m1) => {
+            with_source!(param(1, m1)?, a => Some(relative_base_offset(a, next)))
+        },
+
+        Instruction::Halt => Some(Box::new(|computer: &mut Computer<I, W>| {
+            computer.execution_state = ExecutionState::Halted(computer.memory[0].clone());
+            Ok(Outcome::Continue)
+        })),
+    }
+}
+
+/// Add, Multiply, Less Than and Equals: store `f` of the first two
+/// parameters in the third.
+fn store<I, W, A, B, C, F>(a: A, b: B, c: C, next: usize, f: F) -> Op<I, W>
+    where I: ComputerIo<W>,
+          W: Word,
+          A: Source<W>,
+          B: Source<W>,
+          C: Target,
+          F: Fn(OverflowPolicy, &W, &W) -> Result<W, Fault> + Send + 'static
+{
+    Box::new(move |computer| {
+        let x = a.read(&computer.memory, computer.relative_base)?;
+        let y = b.read(&computer.memory, computer.relative_base)?;
+        let result = f(computer.overflow_policy, &x, &y)?;
+        let address = c.address(&computer.memory, computer.relative_base)?;
+        computer.memory.set(address, result);
+        computer.instruction_pointer = next;
+        Ok(Outcome::Wrote(address))
+    })
+}
+
+fn read<I, W, C>(c: C, next: usize) -> Op<I, W>
+    where I: ComputerIo<W>,
+          W: Word,
+          C: Target
+{
+    Box::new(move |computer| {
+        let input = match computer.pending_input.pop_front() {
+            Some(input) => Some(input),
+            None => computer.io_system.try_read("Enter number: "),
+        };
+
+        match input {
+            Some(input) => {
+                let address = c.address(&computer.memory, computer.relative_base)?;
+                computer.memory.set(address, input);
+                computer.instruction_pointer = next;
+                Ok(Outcome::Wrote(address))
+            },
+            None => {
+                computer.execution_state = ExecutionState::WaitingOnInput;
+                Ok(Outcome::Continue)
+            }
+        }
+    })
+}
+
+fn write<I, W, A>(a: A, next: usize) -> Op<I, W>
+    where I: ComputerIo<W>,
+          W: Word,
+          A: Source<W>
+{
+    Box::new(move |computer| {
+        let value = a.read(&computer.memory, computer.relative_base)?;
+        computer.io_system.write(value.clone());
+        computer.instruction_pointer = next;
+        Ok(Outcome::Output(value))
+    })
+}
+
+/// Jump If True and Jump If False: jump to the second parameter if
+/// `condition` holds for the first.
+fn jump<I, W, A, B, F>(a: A, b: B, next: usize, condition: F) -> Op<I, W>
+    where I: ComputerIo<W>,
+          W: Word,
+          A: Source<W>,
+          B: Source<W>,
+          F: Fn(&W) -> bool + Send + 'static
+{
+    Box::new(move |computer| {
+        let x = a.read(&computer.memory, computer.relative_base)?;
+        if condition(&x) {
+            let target = b.read(&computer.memory, computer.relative_base)?;
+            computer.jump_to(&target)?;
+        } else {
+            computer.instruction_pointer = next;
+        }
+        Ok(Outcome::Continue)
+    })
+}
+
+fn relative_base_offset<I, W, A>(a: A, next: usize) -> Op<I, W>
+    where I: ComputerIo<W>,
+          W: Word,
+          A: Source<W>
+{
+    Box::new(move |computer| {
+        let offset = a.read(&computer.memory, computer.relative_base)?;
+        let offset = offset.to_i64().ok_or(Fault::ArithmeticOverflow)?;
+        computer.relative_base = computer.relative_base.checked_add(offset).ok_or(Fault::ArithmeticOverflow)?;
+        computer.instruction_pointer = next;
+        Ok(Outcome::Continue)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble, QueueIo, RingBufferTracer};
+
+    /// The programs from the interpreter's tests, plus a few more, along
+    /// with their input.
+    fn test_programs() -> Vec<(Vec<i64>, Vec<i64>)> {
+        let mut programs = vec![
+            (vec![109, 10, 203, 2, 21202, 2, 2, 3, 204, 3, 99], vec![21]),
+            (vec![109, 3, 2105, 1, 4, 99, 99, 8, 104, 7, 99], vec![]),
+            (vec![1101, 1, 1, 5, 98, 0], vec![]),
+            (vec![-1], vec![]),
+            (vec![104, 7, 10001, 0, 0, 0, 99], vec![]),
+            (vec![399, 0], vec![]),
+            (vec![1, -3, 0, 0, 99], vec![]),
+            (vec![109, -10, 204, 2, 99], vec![]),
+            (vec![1105, 1, -4, 99], vec![]),
+            (vec![1105, 1, 1000, 99], vec![]),
+            (vec![1101, 1, 1, 0], vec![]),
+            (vec![1101, i64::MAX, 1, 0, 99], vec![]),
+            (vec![1102, i64::MAX, 2, 0, 99], vec![]),
+            (vec![1102, i64::MIN, 2, 0, 99], vec![]),
+            (vec![109, i64::MAX, 109, 1, 99], vec![]),
+            (vec![104, 1, 104, 2, 99], vec![]),
+            (vec![1101, 2, 3, 0, 104, 0, 99], vec![]),
+            (vec![3, 9, 3, 10, 4, 9, 4, 10, 99, 0, 0], vec![10, 20]),
+            (vec![3, 0, 99], vec![]),
+            (vec![109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99], vec![]),
+            (vec![1102, 34915192, 34915192, 7, 4, 7, 99, 0], vec![]),
+            (vec![104, 1125899906842624, 99], vec![]),
+            (vec![1101, 20, 22, 1_000_000_000_000, 4, 1_000_000_000_000, 99], vec![]),
+            // Compares its input with 8, through a Position mode jump.
+            (vec![3, 12, 6, 12, 15, 1, 13, 14, 13, 4, 13, 99, -1, 0, 1, 9], vec![0]),
+            (vec![3, 12, 6, 12, 15, 1, 13, 14, 13, 4, 13, 99, -1, 0, 1, 9], vec![5]),
+        ];
+
+        let assembled = [
+            // Overwrites its own Write with a Halt.
+            "loop:   out #1
+                     add #99, #0, [loop]
+                     jt #1, #loop",
+            // Changes the mode of its own Write.
+            "code:   out #9
+                     jt [done], #stop
+                     add #1, #0, [done]
+                     add #4, #0, [code]
+                     jt #1, #code
+             stop:   hlt
+             done:   db 0",
+            // Modifies the parameter of an instruction it runs repeatedly.
+            "loop:   out [0]
+                     add [1], #1, [1]
+                     lt [1], #6, [flag]
+                     jt [flag], #loop
+                     hlt
+             flag:   db 0",
+            // Calls a subroutine through the stack, returning through a
+            // Relative mode jump.
+            "        arb #100
+                     add #ret1, #0, rb+0
+                     jt #1, #double
+             ret1:   out [value]
+                     add #ret2, #0, rb+0
+                     jt #1, #double
+             ret2:   out [value]
+                     hlt
+             double: mul [value], #2, [value]
+                     jt #1, rb+0
+             value:  db 3",
+        ];
+
+        for source in &assembled {
+            programs.push((assemble(source).unwrap(), vec![]));
+        }
+        programs
+    }
+
+    fn load(program: &[i64], input: &[i64]) -> Computer<QueueIo> {
+        let mut io = QueueIo::new();
+        io.extend_input(input.iter().copied());
+        Computer::load_program(program.to_vec(), io)
+    }
+
+    fn assert_same_state(interpreted: &Computer<QueueIo>, compiled: &Computer<QueueIo>, program: &[i64]) {
+        assert_eq!(interpreted.execution_state, compiled.execution_state, "{:?}", program);
+        assert_eq!(interpreted.instruction_pointer, compiled.instruction_pointer, "{:?}", program);
+        assert_eq!(interpreted.relative_base, compiled.relative_base, "{:?}", program);
+        assert_eq!(interpreted.memory, compiled.memory, "{:?}", program);
+        assert_eq!(interpreted.io_system.output(), compiled.io_system.output(), "{:?}", program);
+    }
+
+    #[test]
+    pub fn compiled_code_matches_the_interpreter_step_by_step() {
+        for policy in &[OverflowPolicy::Fault, OverflowPolicy::Wrapping, OverflowPolicy::Saturating] {
+            for (program, input) in test_programs() {
+                let mut interpreted = load(&program, &input);
+                let mut compiled = load(&program, &input);
+                interpreted.set_overflow_policy(*policy);
+                compiled.set_overflow_policy(*policy);
+                compiled.compile();
+
+                for _ in 0..1000 {
+                    let state = interpreted.step();
+                    assert_eq!(compiled.step(), state, "{:?}", program);
+                    assert_same_state(&interpreted, &compiled, &program);
+                    if state != ExecutionState::Running {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    pub fn compiled_code_matches_the_interpreter_for_each_way_of_running() {
+        for (program, input) in test_programs() {
+            let mut interpreted = load(&program, &input);
+            let mut compiled = load(&program, &input);
+            compiled.compile();
+            assert_eq!(compiled.run_with_budget(5), interpreted.run_with_budget(5));
+            assert_eq!(compiled.run_until_output(), interpreted.run_until_output());
+            assert_eq!(compiled.run(), interpreted.run());
+            assert_same_state(&interpreted, &compiled, &program);
+
+            // Waiting on input can be resumed.
+            interpreted.provide_input(8);
+            compiled.provide_input(8);
+            assert_eq!(compiled.run(), interpreted.run());
+            assert_same_state(&interpreted, &compiled, &program);
+        }
+    }
+
+    #[test]
+    pub fn reachable_code_is_compiled_ahead_of_time() {
+        let program = assemble("
+                    in [n]
+            loop:   out [n]
+                    add [n], #-1, [n]
+                    jt [n], #loop
+                    hlt
+            n:      db 0
+        ").unwrap();
+
+        let mut computer = load(&program, &[3]);
+        assert_eq!(computer.compiled_instructions(), 0);
+        computer.compile();
+        assert_eq!(computer.compiled_instructions(), 5);
+        assert_eq!(computer.run(), ExecutionState::Halted(3));
+        assert_eq!(computer.io_system.output(), &[3, 2, 1]);
+
+        computer.discard_compiled_code();
+        assert_eq!(computer.compiled_instructions(), 0);
+    }
+
+    #[test]
+    pub fn computed_jump_targets_are_compiled_when_reached() {
+        // Jumps to the address in `target`, which is only known at run time.
+        let program = assemble("
+                    jt #1, [target]
+                    hlt
+            there:  out #5
+                    hlt
+            target: db there
+        ").unwrap();
+
+        let mut computer = load(&program, &[]);
+        computer.compile();
+        assert_eq!(computer.compiled_instructions(), 2);
+        computer.run();
+        assert_eq!(computer.compiled_instructions(), 4);
+        assert_eq!(computer.io_system.output(), &[5]);
+    }
+
+    #[test]
+    pub fn overwritten_instructions_are_interpreted() {
+        // Increments the parameter of its own Write each time round.
+        let program = assemble("
+            loop:   out [0]
+                    add [1], #1, [1]
+                    lt [1], #3, [flag]
+                    jt [flag], #loop
+                    hlt
+            flag:   db 0
+        ").unwrap();
+
+        let mut computer = load(&program, &[]);
+        computer.compile();
+        assert_eq!(computer.compiled_instructions(), 5);
+        computer.run();
+        assert_eq!(computer.compiled_instructions(), 4);
+        // The parameter of the Write is 0, then 1, then 2.
+        assert_eq!(computer.io_system.output(), &[4, 1, 1001]);
+    }
+
+    #[test]
+    pub fn tracing_and_history_use_the_interpreter() {
+        let mut computer = load(&[1101, 2, 3, 0, 104, 0, 99], &[]);
+        computer.compile();
+        computer.set_tracer(Box::new(RingBufferTracer::new(10)));
+        computer.enable_history(10);
+        assert_eq!(computer.run(), ExecutionState::Halted(5));
+
+        assert_eq!(computer.history_len(), 3);
+        while computer.step_back() {}
+        assert_eq!(computer.memory()[0], 1101);
+        computer.disable_history();
+        computer.take_tracer();
+        assert_eq!(computer.run(), ExecutionState::Halted(5));
+        assert_eq!(computer.io_system.output(), &[0, 0]);
+    }
+
+    #[test]
+    pub fn writes_after_restore_and_step_back_are_safe() {
+        // Restoring leaves the compiled code empty, but writes near address
+        // 0 still try to invalidate it.
+        let mut computer = load(&[1101, 5, 6, 0, 99], &[]);
+        computer.compile();
+        computer.enable_history(10);
+        let snapshot = computer.snapshot();
+        computer.restore(&snapshot);
+        assert_eq!(computer.run(), ExecutionState::Halted(11));
+
+        while computer.step_back() {}
+        assert_eq!(computer.memory()[0], 1101);
+        assert_eq!(computer.run(), ExecutionState::Halted(11));
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use crate::disassembler::disassemble_with_starts;
+use crate::{Line, Memory};
+
+/// How often one address was used.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Hits {
+    /// The number of instructions executed that started at the address.
+    pub executed: u64,
+    /// The number of times it was read by a Position or Relative mode
+    /// parameter.
+    pub read: u64,
+    pub written: u64,
+}
+
+impl Hits {
+    fn add(&mut self, other: Hits) {
+        self.executed += other.executed;
+        self.read += other.read;
+        self.written += other.written;
+    }
+}
+
+/// A record of which addresses a computer has executed as instructions,
+/// read as data and written, kept when coverage is enabled.
+///
+/// Only instructions that completed are counted as executed, so a Read
+/// that had to wait for input counts once, when it gets some, and an
+/// instruction that faulted does not count. Undoing instructions with
+/// `Computer::step_back` does not undo their hits.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Coverage {
+    hits: BTreeMap<usize, Hits>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the hits for `address`, which are all 0 if it was not used.
+    pub fn hits(&self, address: usize) -> Hits {
+        self.hits.get(&address).copied().unwrap_or_default()
+    }
+
+    /// Returns every address that was used, in address order.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, Hits)> + '_ {
+        self.hits.iter().map(|(&address, &hits)| (address, hits))
+    }
+
+    /// Returns the addresses of the instructions that were executed.
+    /// Comparing these for two runs shows which code only one of them used.
+    pub fn executed_addresses(&self) -> BTreeSet<usize> {
+        self.iter()
+            .filter(|(_, hits)| hits.executed > 0)
+            .map(|(address, _)| address)
+            .collect()
+    }
+
+    pub(crate) fn record_execute(&mut self, address: usize) {
+        self.hits.entry(address).or_default().executed += 1;
+    }
+
+    pub(crate) fn record_read(&mut self, address: usize) {
+        self.hits.entry(address).or_default().read += 1;
+    }
+
+    pub(crate) fn record_write(&mut self, address: usize) {
+        self.hits.entry(address).or_default().written += 1;
+    }
+
+    /// Returns a disassembly of `memory` with the hits for each line, then
+    /// any addresses beyond the dense part of memory that were used. An
+    /// instruction's reads and writes are those of all its words, which
+    /// will only be non-zero if the program modifies its own code. Words of
+    /// data which are 0 and were never used are left out, as memory that
+    /// grew past the program when it was written to is full of them.
+    ///
+    /// Everything that was executed is disassembled as an instruction, so
+    /// `memory` should be the computer's memory at the end of the run, to
+    /// show any code the program patched in as it was executed.
+    pub fn report(&self, memory: &Memory) -> String {
+        let program = memory.dense();
+        let mut starts = vec![0];
+        starts.extend(self.executed_addresses());
+
+        let mut report = String::new();
+        writeln!(report, "{:>8} {:>8} {:>8}", "EXEC", "READ", "WRITE").expect("Writing to a String cannot fail");
+
+        let mut instructions = 0;
+        let mut executed = 0;
+        for line in disassemble_with_starts(program, &starts) {
+            let mut hits = Hits::default();
+            for address in line.address()..line.address() + line.word_count() {
+                hits.add(Hits { executed: 0, ..self.hits(address) });
+            }
+            hits.executed = self.hits(line.address()).executed;
+
+            match line {
+                Line::Instruction { .. } => {
+                    instructions += 1;
+                    if hits.executed > 0 {
+                        executed += 1;
+                    }
+                },
+                Line::Data { value: 0, .. } if hits == Hits::default() => continue,
+                Line::Data { .. } => {},
+            }
+            write_line(&mut report, hits, &line);
+        }
+
+        for (address, hits) in self.hits.range(program.len()..) {
+            write_line(&mut report, *hits, &Line::Data { address: *address, value: memory.get(*address) });
+        }
+
+        writeln!(report, "{} of {} instructions executed", executed, instructions).expect("Writing to a String cannot fail");
+        report
+    }
+}
+
+/// Writes one line of a report, showing counts of 0 as `-` so that the
+/// lines that were used stand out.
+fn write_line(report: &mut String, hits: Hits, line: &Line) {
+    let count = |count: u64| if count == 0 { "-".to_string() } else { count.to_string() };
+    writeln!(report, "{:>8} {:>8} {:>8} {}", count(hits.executed), count(hits.read), count(hits.written), line)
+        .expect("Writing to a String cannot fail");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble, Computer, ExecutionState, QueueIo};
+
+    fn run_with_coverage(program: Vec<i64>, input: Vec<i64>) -> Computer<QueueIo> {
+        let mut io = QueueIo::new();
+        io.extend_input(input);
+        let mut computer = Computer::load_program(program, io);
+        computer.enable_coverage();
+        computer.run();
+        computer
+    }
+
+    #[test]
+    pub fn executes_reads_and_writes_are_counted() {
+        let program = assemble("
+                    in [n]
+            loop:   out [n]
+                    add [n], #-1, [n]
+                    jt [n], #loop
+                    hlt
+            n:      db 0
+        ").unwrap();
+
+        let computer = run_with_coverage(program, vec![3]);
+        let coverage = computer.coverage().unwrap();
+        assert_eq!(coverage.hits(0), Hits { executed: 1, read: 0, written: 0 });
+        assert_eq!(coverage.hits(2), Hits { executed: 3, read: 0, written: 0 });
+        assert_eq!(coverage.hits(3), Hits::default(), "Operand words are not counted");
+        assert_eq!(coverage.hits(11), Hits { executed: 1, read: 0, written: 0 });
+        // Read by OUT, ADD and JT on each pass, written by IN and each ADD.
+        assert_eq!(coverage.hits(12), Hits { executed: 0, read: 9, written: 4 });
+        assert_eq!(coverage.executed_addresses().into_iter().collect::<Vec<_>>(), vec![0, 2, 4, 8, 11]);
+    }
+
+    #[test]
+    pub fn waiting_and_faulting_instructions_are_not_executed() {
+        let mut computer = run_with_coverage(vec![3, 5, 4, -1, 99, 0], vec![]);
+        assert_eq!(computer.execution_state, ExecutionState::WaitingOnInput);
+        assert_eq!(computer.coverage().unwrap().hits(0), Hits::default());
+
+        computer.provide_input(1);
+        computer.run();
+        let coverage = computer.coverage().unwrap();
+        assert_eq!(coverage.hits(0), Hits { executed: 1, read: 0, written: 0 });
+        assert_eq!(coverage.hits(2), Hits::default(), "The Write faulted");
+        assert!(matches!(computer.execution_state, ExecutionState::Faulted { .. }));
+    }
+
+    #[test]
+    pub fn compiled_code_is_not_used_while_recording() {
+        let program = assemble("
+                    add #3, #0, [n]
+            loop:   add [n], #-1, [n]
+                    jt [n], #loop
+                    hlt
+            n:      db 0
+        ").unwrap();
+
+        let mut computer = Computer::load_program(program, QueueIo::new());
+        computer.compile();
+        computer.enable_coverage();
+        computer.run();
+        assert_eq!(computer.coverage().unwrap().hits(4).executed, 3);
+
+        computer.disable_coverage();
+        assert!(computer.coverage().is_none());
+    }
+
+    #[test]
+    pub fn report_overlays_the_disassembly() {
+        // Patches a Halt into address 7 and jumps to it.
+        let computer = run_with_coverage(vec![1101, 99, 0, 7, 1106, 0, 7, 0], vec![]);
+        let report = computer.coverage().unwrap().report(computer.memory());
+        assert_eq!(report.lines().collect::<Vec<_>>(), vec![
+            "    EXEC     READ    WRITE",
+            "       1        -        -      0: ADD #99, #0, [7]",
+            "       1        -        -      4: JF #0, #7    ; -> 7",
+            "       1        -        1      7: HLT",
+            "3 of 3 instructions executed",
+        ]);
+
+        // Writes beyond the program.
+        let computer = run_with_coverage(vec![1101, 5, 0, 100, 4, 100, 99], vec![]);
+        let report = computer.coverage().unwrap().report(computer.memory());
+        assert!(report.contains("       1        -        -      4: OUT [100]\n"));
+        assert!(report.contains("       -        1        1    100: DATA 5\n"));
+        assert!(!report.contains("DATA 0"));
+        assert!(report.ends_with("3 of 3 instructions executed\n"));
+
+        let report = Coverage::new().report(&Memory::new(vec![104, 1, 99, 7]));
+        assert!(report.contains("       -        -        -      3: DATA 7\n"));
+        assert!(report.ends_with("0 of 2 instructions executed\n"));
+    }
+}
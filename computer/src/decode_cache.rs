@@ -0,0 +1,118 @@
+use crate::Instruction;
+
+/// The instructions that a computer has already decoded, keyed by the
+/// address of their opcode word, so that loops do not pay for decoding on
+/// every pass.
+///
+/// An entry is only valid while the opcode word is unchanged, so the
+/// computer must invalidate the address whenever it writes to memory.
+/// Parameter words are read from memory at execution time and are not
+/// cached, so writes to them need no special handling.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DecodeCache {
+    entries: Vec<Option<Instruction>>,
+}
+
+impl DecodeCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, address: usize) -> Option<Instruction> {
+        self.entries.get(address).copied().flatten()
+    }
+
+    pub fn insert(&mut self, address: usize, instruction: Instruction) {
+        if address >= self.entries.len() {
+            self.entries.resize(address + 1, None);
+        }
+        self.entries[address] = Some(instruction);
+    }
+
+    /// Forgets the instruction at `address`, if there is one. Called when
+    /// the word there is written to.
+    pub fn invalidate(&mut self, address: usize) {
+        if let Some(entry) = self.entries.get_mut(address) {
+            *entry = None;
+        }
+    }
+
+    /// Forgets every instruction at or beyond `len`. Called when memory
+    /// shrinks, as the words there will read as 0 if it grows again.
+    pub fn truncate(&mut self, len: usize) {
+        self.entries.truncate(len);
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble, Computer, ExecutionState, QueueIo};
+
+    #[test]
+    pub fn invalidated_entries_are_forgotten() {
+        let mut cache = DecodeCache::new();
+        cache.insert(3, Instruction::Halt);
+        assert_eq!(cache.get(3), Some(Instruction::Halt));
+        assert_eq!(cache.get(2), None);
+        assert_eq!(cache.get(100), None);
+
+        cache.invalidate(3);
+        cache.invalidate(100);
+        assert_eq!(cache.get(3), None);
+
+        cache.insert(3, Instruction::Halt);
+        cache.truncate(3);
+        assert_eq!(cache.get(3), None);
+    }
+
+    #[test]
+    pub fn cached_computer_sees_self_modifying_code() {
+        // The first pass through the loop outputs 1, then overwrites the
+        // Write with a Halt. The cached Write must not run again.
+        let program = assemble("
+            loop:   out #1
+                    add #99, #0, [loop]
+                    jt #1, #loop
+        ").unwrap();
+
+        let mut computer = Computer::load_program(program, QueueIo::new());
+        computer.enable_decode_cache();
+        assert_eq!(computer.run(), ExecutionState::Halted(99));
+        assert_eq!(computer.io_system.output(), &[1]);
+    }
+
+    #[test]
+    pub fn cache_is_invalidated_by_undo_and_restore() {
+        // Outputs 9, then changes the Write at `code` to Position mode and
+        // runs it again, which outputs 1101 (the word at address 9).
+        let program = assemble("
+            code:   out #9
+                    jt [done], #stop
+                    add #1, #0, [done]
+                    add #4, #0, [code]
+                    jt #1, #code
+            stop:   hlt
+            done:   db 0
+        ").unwrap();
+
+        let mut computer = Computer::load_program(program, QueueIo::new());
+        computer.enable_decode_cache();
+        computer.enable_history(100);
+        let snapshot = computer.snapshot();
+        assert_eq!(computer.run(), ExecutionState::Halted(4));
+        assert_eq!(computer.io_system.drain_output().collect::<Vec<_>>(), vec![9, 1101]);
+
+        while computer.step_back() {}
+        assert_eq!(computer.run(), ExecutionState::Halted(4));
+        assert_eq!(computer.io_system.drain_output().collect::<Vec<_>>(), vec![9, 1101]);
+
+        computer.restore(&snapshot);
+        assert_eq!(computer.run(), ExecutionState::Halted(4));
+        assert_eq!(computer.io_system.drain_output().collect::<Vec<_>>(), vec![9, 1101]);
+    }
+}
@@ -0,0 +1,292 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use crate::{Instruction, ParameterMode, Word};
+
+/// One line of a disassembly listing.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Line {
+    /// An instruction starting at `address`, followed by its raw operands.
+    Instruction { address: usize, instruction: Instruction, operands: Vec<i64> },
+    /// A word that is not part of any instruction.
+    Data { address: usize, value: i64 },
+}
+
+impl Line {
+    /// Returns the address of the first word on this line.
+    pub fn address(&self) -> usize {
+        match *self {
+            Line::Instruction { address, .. } => address,
+            Line::Data { address, .. } => address,
+        }
+    }
+
+    /// Returns the number of words covered by this line.
+    pub fn word_count(&self) -> usize {
+        match self {
+            Line::Instruction { operands, .. } => operands.len() + 1,
+            Line::Data { .. } => 1,
+        }
+    }
+
+    /// Returns the target of the line's jump, if it is a jump whose target
+    /// is known without running the program.
+    pub fn jump_target(&self) -> Option<usize> {
+        match self {
+            Line::Instruction { instruction, operands, .. } => static_jump_target(*instruction, operands),
+            Line::Data { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Line::Instruction { address, instruction, operands } => {
+                write!(f, "{:>6}: {}", address, instruction.mnemonic())?;
+                let modes = instruction.parameter_modes();
+                for (i, (mode, value)) in modes.iter().zip(operands).enumerate() {
+                    let separator = if i == 0 { " " } else { ", " };
+                    write!(f, "{}{}", separator, format_operand(*mode, value))?;
+                }
+                if let Some(target) = self.jump_target() {
+                    write!(f, "    ; -> {}", target)?;
+                }
+                Ok(())
+            },
+            Line::Data { address, value } => write!(f, "{:>6}: DATA {}", address, value),
+        }
+    }
+}
+
+/// Renders the instruction with placeholders for its operands, which the
+/// instruction does not know, e.g. `ADD [_], #_, rb+_`. Use `Line` to get
+/// the operands too.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.mnemonic())?;
+        for (i, mode) in self.parameter_modes().iter().enumerate() {
+            let separator = if i == 0 { " " } else { ", " };
+            let placeholder = match mode {
+                ParameterMode::Position => "[_]",
+                ParameterMode::Immediate => "#_",
+                ParameterMode::Relative => "rb+_",
+            };
+            write!(f, "{}{}", separator, placeholder)?;
+        }
+        Ok(())
+    }
+}
+
+/// Renders an operand with the sigil for its mode: `[10]` for Position,
+/// `#5` for Immediate and `rb+3` (or `rb-3`) for Relative.
+pub(crate) fn format_operand<W: Word>(mode: ParameterMode, value: &W) -> String {
+    match mode {
+        ParameterMode::Position => format!("[{}]", value),
+        ParameterMode::Immediate => format!("#{}", value),
+        ParameterMode::Relative => {
+            let value = value.to_string();
+            match value.strip_prefix('-') {
+                Some(magnitude) => format!("rb-{}", magnitude),
+                None => format!("rb+{}", value),
+            }
+        },
+    }
+}
+
+/// Disassembles a program into a listing.
+///
+/// Starting from address 0, we follow the flow of control through the
+/// program, including any jumps whose targets are Immediate mode parameters.
+/// Everything reached that way is listed as an instruction. Anything else
+/// is listed as an instruction if it decodes (and does not overlap code that
+/// was reached), otherwise it is listed as `DATA`.
+pub fn disassemble(program: &[i64]) -> Vec<Line> {
+    disassemble_with_starts(program, &[0])
+}
+
+/// Disassembles a program like `disassemble`, but follows the flow of
+/// control from each of `starts` rather than just address 0.
+pub(crate) fn disassemble_with_starts(program: &[i64], starts: &[usize]) -> Vec<Line> {
+    let reachable = starts.iter()
+        .flat_map(|&start| find_reachable_instructions(program, start))
+        .collect::<BTreeSet<_>>();
+    let mut lines = Vec::new();
+    let mut address = 0;
+
+    while address < program.len() {
+        let line = match decode_at(program, address) {
+            Some((instruction, operands)) => {
+                let end = address + operands.len() + 1;
+                let overlaps_reachable = reachable.range(address + 1..end).next().is_some();
+                if reachable.contains(&address) || !overlaps_reachable {
+                    Line::Instruction { address, instruction, operands }
+                } else {
+                    Line::Data { address, value: program[address] }
+                }
+            },
+            None => Line::Data { address, value: program[address] },
+        };
+
+        address += line.word_count();
+        lines.push(line);
+    }
+
+    lines
+}
+
+/// Disassembles up to `count` lines, starting at `address` and simply
+/// working forwards from there. This is useful for showing the code around
+/// the instruction pointer, which is known to be the start of an instruction.
+pub fn disassemble_from(program: &[i64], address: usize, count: usize) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut address = address;
+
+    while address < program.len() && lines.len() < count {
+        let line = match decode_at(program, address) {
+            Some((instruction, operands)) => Line::Instruction { address, instruction, operands },
+            None => Line::Data { address, value: program[address] },
+        };
+
+        address += line.word_count();
+        lines.push(line);
+    }
+
+    lines
+}
+
+/// Decodes the instruction at `address`, if there is one and all of its
+/// operands lie within the program.
+pub(crate) fn decode_at(program: &[i64], address: usize) -> Option<(Instruction, Vec<i64>)> {
+    let instruction = Instruction::decode(program[address]).ok()?;
+    let end = address + instruction.parameter_modes().len() + 1;
+    if end > program.len() {
+        return None;
+    }
+
+    Some((instruction, program[address + 1..end].to_vec()))
+}
+
+/// Returns the target of a jump instruction if it is an Immediate mode
+/// parameter (i.e. it cannot change at runtime).
+pub(crate) fn static_jump_target(instruction: Instruction, operands: &[i64]) -> Option<usize> {
+    match instruction {
+        Instruction::JumpIfTrue(_, ParameterMode::Immediate)
+            | Instruction::JumpIfFalse(_, ParameterMode::Immediate) if operands[1] >= 0 => {
+            Some(operands[1] as usize)
+        },
+        _ => None,
+    }
+}
+
+/// Follows the flow of control from `start` and returns the addresses of
+/// all instructions that can be statically shown to be executed.
+pub(crate) fn find_reachable_instructions(program: &[i64], start: usize) -> BTreeSet<usize> {
+    let mut reachable = BTreeSet::new();
+    let mut pending = vec![start];
+
+    while let Some(address) = pending.pop() {
+        if address >= program.len() || !reachable.insert(address) {
+            continue;
+        }
+
+        let (instruction, operands) = match decode_at(program, address) {
+            Some(decoded) => decoded,
+            None => {
+                reachable.remove(&address);
+                continue;
+            }
+        };
+
+        let next = address + operands.len() + 1;
+        let target = static_jump_target(instruction, &operands);
+
+        match instruction {
+            Instruction::Halt => {},
+            Instruction::JumpIfTrue(ParameterMode::Immediate, _) => {
+                // The condition is a constant, so only one way is possible.
+                if operands[0] != 0 {
+                    pending.extend(target);
+                } else {
+                    pending.push(next);
+                }
+            },
+            Instruction::JumpIfFalse(ParameterMode::Immediate, _) => {
+                if operands[0] == 0 {
+                    pending.extend(target);
+                } else {
+                    pending.push(next);
+                }
+            },
+            _ => {
+                pending.extend(target);
+                pending.push(next);
+            }
+        }
+    }
+
+    reachable
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ParameterMode::*;
+
+    fn listing(program: &[i64]) -> Vec<String> {
+        disassemble(program).iter().map(|line| line.to_string().trim().to_string()).collect()
+    }
+
+    #[test]
+    pub fn instruction_display_uses_placeholders() {
+        assert_eq!(Instruction::Add(Position, Immediate, Relative).to_string(), "ADD [_], #_, rb+_");
+        assert_eq!(Instruction::Halt.to_string(), "HLT");
+    }
+
+    #[test]
+    pub fn operands_are_rendered_with_mode_sigils() {
+        assert_eq!(listing(&[21001, 10, 5, 3]), vec!["0: ADD [10], #5, rb+3"]);
+        assert_eq!(listing(&[204, -7, 99]), vec!["0: OUT rb-7", "2: HLT"]);
+    }
+
+    #[test]
+    pub fn undecodable_words_are_data() {
+        assert_eq!(listing(&[104, 7, 99, 0, 677]), vec!["0: OUT #7", "2: HLT", "3: DATA 0", "4: DATA 677"]);
+    }
+
+    #[test]
+    pub fn truncated_instructions_are_data() {
+        assert_eq!(listing(&[99, 1101, 1]), vec!["0: HLT", "1: DATA 1101", "2: DATA 1"]);
+    }
+
+    #[test]
+    pub fn jump_targets_are_followed() {
+        // Jumps over a word which would otherwise be decoded as the start
+        // of an ADD that swallows the OUT.
+        let program = [1105, 1, 4, 1, 104, 1, 99];
+        assert_eq!(listing(&program), vec![
+            "0: JT #1, #4    ; -> 4",
+            "3: DATA 1",
+            "4: OUT #1",
+            "6: HLT",
+        ]);
+        assert_eq!(disassemble(&program)[0].jump_target(), Some(4));
+    }
+
+    #[test]
+    pub fn disassemble_from_starts_at_the_given_address() {
+        // Address 3 is in the middle of the ADD if we start from 0.
+        let program = [1101, 1, 1, 104, 5, 99];
+        let lines = disassemble_from(&program, 3, 10).iter().map(|l| l.to_string().trim().to_string()).collect::<Vec<_>>();
+        assert_eq!(lines, vec!["3: OUT #5", "5: HLT"]);
+        assert_eq!(disassemble_from(&program, 3, 1).len(), 1);
+    }
+
+    #[test]
+    pub fn invalid_opcodes_patched_at_runtime_are_data() {
+        // The start of day 5's program adds its input to the word at address
+        // 6 before executing it. The disassembler does not follow writes, so
+        // it sees 1100, whose opcode 00 is invalid, and lists it as data.
+        let program = [3,225,1,225,6,6,1100,1,238,225,104,0];
+        assert_eq!(listing(&program)[..3], ["0: IN [225]", "2: ADD [225], [6], [6]", "6: DATA 1100"]);
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::VecDeque;
+use crate::Word;
+
+/// Everything needed to undo one executed instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct UndoRecord<W> {
+    pub instruction_pointer: usize,
+    pub relative_base: i64,
+    /// The size of memory before the instruction, which may have grown it.
+    pub memory_len: usize,
+    /// The address and previous value of each memory write, in the order
+    /// they were made.
+    pub memory_writes: Vec<(usize, W)>,
+    /// The value read, if the instruction was a Read.
+    pub input: Option<W>,
+}
+
+/// The undo log kept by a computer when history is enabled.
+#[derive(Debug, Clone)]
+pub(crate) struct History<W> {
+    capacity: usize,
+    records: VecDeque<UndoRecord<W>>,
+    /// The record for the instruction being executed.
+    current: Option<UndoRecord<W>>,
+}
+
+impl<W: Word> History<W> {
+    /// Creates an empty log that remembers up to `capacity` instructions.
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity, records: VecDeque::new(), current: None }
+    }
+
+    /// Starts recording an instruction which is about to be executed.
+    pub fn begin(&mut self, instruction_pointer: usize, relative_base: i64, memory_len: usize) {
+        self.current = Some(UndoRecord {
+            instruction_pointer,
+            relative_base,
+            memory_len,
+            memory_writes: Vec::new(),
+            input: None,
+        });
+    }
+
+    pub fn record_write(&mut self, address: usize, old: W) {
+        if let Some(record) = &mut self.current {
+            record.memory_writes.push((address, old));
+        }
+    }
+
+    pub fn record_input(&mut self, value: W) {
+        if let Some(record) = &mut self.current {
+            record.input = Some(value);
+        }
+    }
+
+    /// Adds the instruction being recorded to the log, forgetting the
+    /// oldest instruction if the log is full.
+    pub fn commit(&mut self) {
+        if let Some(record) = self.current.take() {
+            self.records.push_back(record);
+            if self.records.len() > self.capacity {
+                self.records.pop_front();
+            }
+        }
+    }
+
+    /// Throws away the instruction being recorded, which did not change
+    /// anything.
+    pub fn discard(&mut self) {
+        self.current = None;
+    }
+
+    /// Removes and returns the most recently executed instruction.
+    pub fn pop(&mut self) -> Option<UndoRecord<W>> {
+        self.records.pop_back()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble, Computer, ExecutionState, Fault, QueueIo};
+
+    #[test]
+    pub fn log_forgets_the_oldest_instructions() {
+        let mut history = History::<i64>::new(2);
+        for ip in 0..3 {
+            history.begin(ip, 0, 10);
+            history.commit();
+        }
+        history.begin(3, 0, 10);
+        history.discard();
+
+        assert_eq!(history.len(), 2);
+        assert_eq!(history.pop().map(|r| r.instruction_pointer), Some(2));
+        assert_eq!(history.pop().map(|r| r.instruction_pointer), Some(1));
+        assert_eq!(history.pop(), None);
+    }
+
+    #[test]
+    pub fn step_back_undoes_memory_and_registers() {
+        let program = assemble("
+            arb #5
+            add #2, #3, rb+100
+            hlt
+        ").unwrap();
+        let mut computer = Computer::load_program(program.clone(), QueueIo::new());
+        computer.enable_history(100);
+        assert_eq!(computer.run(), ExecutionState::Halted(109));
+        assert_eq!(computer.history_len(), 3);
+
+        assert!(computer.step_back());
+        assert_eq!(computer.execution_state, ExecutionState::Running);
+        assert_eq!(computer.instruction_pointer(), 6);
+        assert_eq!(computer.memory()[105], 5);
+
+        assert!(computer.step_back());
+        assert_eq!(computer.instruction_pointer(), 2);
+        assert_eq!(computer.relative_base(), 5);
+        assert_eq!(computer.memory().dense(), &program[..], "Memory grown by the write is removed");
+
+        assert!(computer.step_back());
+        assert_eq!(computer.relative_base(), 0);
+        assert!(!computer.step_back());
+    }
+
+    #[test]
+    pub fn stepping_back_over_a_read_replays_the_input() {
+        let program = assemble("
+            loop:   in [value]
+                    out [value]
+                    jt #1, #loop
+            value:  db 0
+        ").unwrap();
+        let mut computer = Computer::load_program(program, QueueIo::new());
+        computer.enable_history(100);
+        computer.provide_input(7);
+        computer.provide_input(8);
+        assert_eq!(computer.run(), ExecutionState::WaitingOnInput);
+        assert_eq!(computer.history_len(), 6, "Waiting on input is not recorded");
+
+        assert!(computer.run_back_to(0));
+        assert_eq!(computer.history_len(), 3);
+        assert_eq!(computer.run_until_output(), Some(8));
+    }
+
+    #[test]
+    pub fn walk_back_from_a_fault_to_the_corrupting_write() {
+        // The ADD overwrites the HLT at address 9 with a bad opcode.
+        let program = assemble("
+                    add #90, #8, [end]
+                    out #1
+                    jt #1, #end
+            end:    hlt
+        ").unwrap();
+        let mut computer = Computer::load_program(program, QueueIo::new());
+        computer.enable_history(100);
+        let state = computer.run();
+        assert_eq!(state, ExecutionState::Faulted { ip: 9, word: 98, fault: Fault::BadOpcode });
+
+        while computer.memory()[9] == 98 {
+            assert!(computer.step_back());
+        }
+        assert_eq!(computer.instruction_pointer(), 0);
+        assert_eq!(computer.memory()[9], 99);
+    }
+
+    #[test]
+    pub fn run_back_to_stops_when_history_runs_out() {
+        let program = assemble("out #1\nout #2\nout #3\nhlt").unwrap();
+        let mut computer = Computer::load_program(program, QueueIo::new());
+        computer.enable_history(2);
+        computer.run();
+
+        assert!(!computer.run_back_to(0));
+        assert_eq!(computer.instruction_pointer(), 4);
+        assert_eq!(computer.history_len(), 0);
+    }
+
+    #[test]
+    pub fn history_is_off_by_default_and_cleared_by_restore() {
+        let program = assemble("out #1\nhlt").unwrap();
+        let mut computer = Computer::load_program(program, QueueIo::new());
+        computer.step();
+        assert!(!computer.step_back());
+
+        let snapshot = computer.snapshot();
+        computer.enable_history(10);
+        computer.step();
+        computer.restore(&snapshot);
+        assert_eq!(computer.history_len(), 0);
+        assert!(!computer.step_back());
+    }
+}
@@ -0,0 +1,281 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use bitflags::bitflags;
+use crate::{Assembly, Program};
+use crate::binary::{invalid_data, read_u8, read_u64, read_i64, write_u64, write_i64};
+
+/// Identifies a file as a program image.
+const MAGIC: &[u8; 4] = b"ICIM";
+const VERSION: u8 = 1;
+
+bitflags! {
+    /// What an image file contains, and how.
+    struct ImageFlags: u8 {
+        /// Words are zigzag varints rather than 8 byte little-endian numbers.
+        const VARINT = 0b00000001;
+        const SYMBOLS = 0b00000010;
+        const ENTRY_POINT = 0b00000100;
+    }
+}
+
+/// How the words of a program are stored in an image file.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub enum WordEncoding {
+    /// Every word takes 8 bytes, little-endian.
+    #[default]
+    LittleEndian,
+    /// Each word takes as many bytes as it needs, 7 bits at a time.
+    /// Intcode programs are mostly small numbers, so this usually makes
+    /// images several times smaller.
+    Varint,
+}
+
+/// A program along with optional debug symbols and an entry point, which
+/// can be stored in a compact binary form that loads much faster than text.
+///
+/// Only images of programs with `i64` words can be saved to files.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Image<W = i64> {
+    pub program: Vec<W>,
+    /// The address of each label, as produced by the assembler.
+    pub symbols: BTreeMap<String, usize>,
+    /// Where execution starts. A computer loaded from an image without one
+    /// starts at address 0.
+    pub entry_point: Option<usize>,
+}
+
+impl<W> From<Vec<W>> for Image<W> {
+    fn from(program: Vec<W>) -> Self {
+        Self { program, symbols: BTreeMap::new(), entry_point: None }
+    }
+}
+
+impl<W> From<Program<W>> for Image<W> {
+    fn from(program: Program<W>) -> Self {
+        Self::from(Vec::from(program))
+    }
+}
+
+impl From<Assembly> for Image {
+    fn from(assembly: Assembly) -> Self {
+        Self { program: assembly.program, symbols: assembly.labels, entry_point: None }
+    }
+}
+
+impl Image {
+    /// Writes the image. Apart from the words when they are varints, all
+    /// numbers are little-endian.
+    pub fn write_to<W: Write>(&self, writer: &mut W, encoding: WordEncoding) -> io::Result<()> {
+        let mut flags = ImageFlags::empty();
+        flags.set(ImageFlags::VARINT, encoding == WordEncoding::Varint);
+        flags.set(ImageFlags::SYMBOLS, !self.symbols.is_empty());
+        flags.set(ImageFlags::ENTRY_POINT, self.entry_point.is_some());
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION, flags.bits()])?;
+
+        write_u64(writer, self.program.len() as u64)?;
+        for &word in &self.program {
+            match encoding {
+                WordEncoding::LittleEndian => write_i64(writer, word)?,
+                WordEncoding::Varint => write_varint(writer, word)?,
+            }
+        }
+
+        if flags.contains(ImageFlags::SYMBOLS) {
+            write_u64(writer, self.symbols.len() as u64)?;
+            for (name, &address) in &self.symbols {
+                write_u64(writer, name.len() as u64)?;
+                writer.write_all(name.as_bytes())?;
+                write_u64(writer, address as u64)?;
+            }
+        }
+
+        if let Some(entry_point) = self.entry_point {
+            write_u64(writer, entry_point as u64)?;
+        }
+        Ok(())
+    }
+
+    /// Reads an image written by `write_to`, in either encoding.
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("Not a program image"));
+        }
+
+        let version = read_u8(reader)?;
+        if version != VERSION {
+            return Err(invalid_data(&format!("Unsupported image version {}", version)));
+        }
+
+        let flags = read_u8(reader)?;
+        let flags = ImageFlags::from_bits(flags)
+            .ok_or_else(|| invalid_data(&format!("Unknown image flags {:#04x}", flags)))?;
+
+        let len = read_u64(reader)?;
+        // Don't trust the length for the allocation, a corrupt file could
+        // claim to contain anything.
+        let mut program = Vec::with_capacity(len.min(1 << 20) as usize);
+        for _ in 0..len {
+            program.push(if flags.contains(ImageFlags::VARINT) {
+                read_varint(reader)?
+            } else {
+                read_i64(reader)?
+            });
+        }
+
+        let mut symbols = BTreeMap::new();
+        if flags.contains(ImageFlags::SYMBOLS) {
+            let count = read_u64(reader)?;
+            for _ in 0..count {
+                let name = read_string(reader)?;
+                let address = read_u64(reader)? as usize;
+                symbols.insert(name, address);
+            }
+        }
+
+        let entry_point = if flags.contains(ImageFlags::ENTRY_POINT) {
+            Some(read_u64(reader)? as usize)
+        } else {
+            None
+        };
+
+        Ok(Self { program, symbols, entry_point })
+    }
+
+    /// Saves the image to a file, replacing it if it exists.
+    pub fn save<P: AsRef<Path>>(&self, path: P, encoding: WordEncoding) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_to(&mut writer, encoding)?;
+        writer.flush()
+    }
+
+    /// Loads an image saved by `save`.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::read_from(&mut BufReader::new(File::open(path)?))
+    }
+}
+
+/// Writes a word as a zigzag encoded LEB128 varint, so that small negative
+/// numbers are as short as small positive ones.
+fn write_varint<W: Write>(writer: &mut W, value: i64) -> io::Result<()> {
+    let mut zigzag = ((value << 1) ^ (value >> 63)) as u64;
+    loop {
+        let byte = (zigzag & 0x7f) as u8;
+        zigzag >>= 7;
+        if zigzag == 0 {
+            return writer.write_all(&[byte]);
+        }
+        writer.write_all(&[byte | 0x80])?;
+    }
+}
+
+fn read_varint<R: Read>(reader: &mut R) -> io::Result<i64> {
+    let mut zigzag = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = read_u8(reader)?;
+        // Only the lowest bit of the tenth byte fits in 64 bits.
+        if shift == 63 && byte & 0x7e != 0 {
+            return Err(invalid_data("Varint is too long"));
+        }
+        zigzag |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok((zigzag >> 1) as i64 ^ -((zigzag & 1) as i64));
+        }
+    }
+    Err(invalid_data("Varint is too long"))
+}
+
+fn read_string<R: Read>(reader: &mut R) -> io::Result<String> {
+    let len = read_u64(reader)?;
+    let mut bytes = Vec::new();
+    reader.take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    String::from_utf8(bytes).map_err(|_| invalid_data("Symbol name is not UTF-8"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble_with_labels, Computer, ExecutionState, QueueIo};
+    use crate::binary::through_temp_file;
+
+    fn round_trip(image: &Image, encoding: WordEncoding) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        image.write_to(&mut bytes, encoding).unwrap();
+        assert_eq!(&Image::read_from(&mut bytes.as_slice()).unwrap(), image);
+        bytes
+    }
+
+    #[test]
+    pub fn both_encodings_round_trip() {
+        let image = Image::from(vec![0, 1, -1, 63, -64, 64, 1 << 40, i64::MAX, i64::MIN]);
+        round_trip(&image, WordEncoding::LittleEndian);
+        round_trip(&image, WordEncoding::Varint);
+    }
+
+    #[test]
+    pub fn varints_are_compact() {
+        let image = Image::from(vec![1101, 1, -1, 99]);
+        let little_endian = round_trip(&image, WordEncoding::LittleEndian);
+        let varint = round_trip(&image, WordEncoding::Varint);
+        // Header and word count, then the words.
+        assert_eq!(little_endian.len(), 14 + 4 * 8);
+        assert_eq!(varint.len(), 14 + 2 + 1 + 1 + 2);
+    }
+
+    #[test]
+    pub fn symbols_and_entry_point_round_trip() {
+        let assembly = assemble_with_labels("
+            value:  db 7
+            start:  out [value]
+                    hlt
+        ").unwrap();
+        let mut image = Image::from(assembly);
+        image.entry_point = Some(image.symbols["start"]);
+        round_trip(&image, WordEncoding::Varint);
+
+        let mut computer = Computer::load_image(image, QueueIo::new());
+        assert_eq!(computer.run(), ExecutionState::Halted(7));
+        assert_eq!(computer.io_system.output(), &[7]);
+    }
+
+    #[test]
+    pub fn read_rejects_snapshots_unknown_flags_and_bad_varints() {
+        let err = Image::read_from(&mut &b"ICSS"[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let err = Image::read_from(&mut &b"ICIM\x01\x80"[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // A varint whose last byte is missing.
+        let err = Image::read_from(&mut &b"ICIM\x01\x01\x01\0\0\0\0\0\0\0\xff"[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        let mut bytes = b"ICIM\x01\x01\x01\0\0\0\0\0\0\0".to_vec();
+        bytes.extend_from_slice(&[0xff; 11]);
+        let err = Image::read_from(&mut bytes.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // Ten bytes, but with bits set that do not fit in a word.
+        let mut bytes = b"ICIM\x01\x01\x01\0\0\0\0\0\0\0".to_vec();
+        bytes.extend_from_slice(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02]);
+        let err = Image::read_from(&mut bytes.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    pub fn save_and_load_a_varint_file_with_symbols() {
+        let mut image = Image::from(vec![104, 5, 99]);
+        image.symbols.insert("main".to_string(), 0);
+
+        let loaded = through_temp_file("image", |path| image.save(path, WordEncoding::Varint), |path| Image::load(path)).unwrap();
+        assert_eq!(loaded, image);
+    }
+}
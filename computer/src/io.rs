@@ -0,0 +1,275 @@
+use std::collections::VecDeque;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use crate::{Computer, Word};
+
+/// Represents the IO that the computer is capable of.
+pub trait ComputerIo<W = i64> {
+    fn try_read(&mut self, message: &str) -> Option<W>;
+    fn write(&mut self, value: W);
+}
+
+/// The default implementation of `ComputerIo` reads from stdin and
+/// writes to stdout.
+pub struct StandardComputerIoSystem { }
+
+impl StandardComputerIoSystem {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl Default for StandardComputerIoSystem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<W: Word> ComputerIo<W> for StandardComputerIoSystem {
+    fn try_read(&mut self, message: &str) -> Option<W> {
+        use std::io::Write;
+        use std::io::{stdout, stdin};
+
+        loop {
+            print!("{}", message);
+            stdout().flush().unwrap();
+            let mut ret = String::new();
+            stdin().read_line(&mut ret).expect("Failed to read from stdin");
+
+            match ret.trim().parse::<W>() {
+                Ok(value) => return Some(value),
+                Err(_) => {
+                    println!("\nNOT A VALID INTEGER. Try again.");
+                }
+            }
+        }
+    }
+
+    fn write(&mut self, value: W) {
+        println!("{}", value);
+    }
+}
+
+/// An implementation of `ComputerIo` that reads from a queue of input
+/// values and collects everything written in an output buffer. Reading
+/// from an empty queue returns `None`, so the computer will wait on input
+/// until more is pushed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueueIo<W = i64> {
+    input: VecDeque<W>,
+    output: Vec<W>,
+}
+
+impl<W: Word> QueueIo<W> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a value to the back of the input queue.
+    pub fn push_input(&mut self, value: W) {
+        self.input.push_back(value);
+    }
+
+    /// Adds several values to the back of the input queue, in order.
+    pub fn extend_input<T>(&mut self, values: T)
+        where T: IntoIterator<Item = W>
+    {
+        self.input.extend(values);
+    }
+
+    /// Removes and returns everything written so far.
+    pub fn drain_output(&mut self) -> std::vec::Drain<'_, W> {
+        self.output.drain(..)
+    }
+
+    /// Returns the most recently written value that has not been drained.
+    pub fn last_output(&self) -> Option<W> {
+        self.output.last().cloned()
+    }
+
+    /// Returns everything written that has not been drained.
+    pub fn output(&self) -> &[W] {
+        &self.output
+    }
+}
+
+impl<W: Word> ComputerIo<W> for QueueIo<W> {
+    fn try_read(&mut self, _message: &str) -> Option<W> {
+        self.input.pop_front()
+    }
+
+    fn write(&mut self, value: W) {
+        self.output.push(value);
+    }
+}
+
+/// An implementation of `ComputerIo` that reads from and writes to
+/// channels, so that computers can run on their own threads and be
+/// wired together. Reads block until a value arrives, or until the
+/// timeout expires if one has been set. If no value arrives (or the
+/// sending end has gone away) the computer will wait on input.
+pub struct ChannelIo<W = i64> {
+    input: Receiver<W>,
+    output: Sender<W>,
+    timeout: Option<Duration>,
+}
+
+impl<W: Word> ChannelIo<W> {
+    pub fn new(input: Receiver<W>, output: Sender<W>) -> Self {
+        Self { input, output, timeout: None }
+    }
+
+    /// Sets the maximum time that a read will block for.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Returns any values that have been sent to this computer but not
+    /// yet read, without blocking.
+    pub fn drain_input(&self) -> Vec<W> {
+        self.input.try_iter().collect()
+    }
+}
+
+impl<W: Word> ComputerIo<W> for ChannelIo<W> {
+    fn try_read(&mut self, _message: &str) -> Option<W> {
+        match self.timeout {
+            Some(timeout) => self.input.recv_timeout(timeout).ok(),
+            None => self.input.recv().ok(),
+        }
+    }
+
+    fn write(&mut self, value: W) {
+        // If nobody is listening any more the output is simply dropped,
+        // in the same way as writing to stdout when it has been closed.
+        let _ = self.output.send(value);
+    }
+}
+
+/// A computer running on its own thread, along with the channel ends
+/// used to talk to it.
+pub struct SpawnedComputer {
+    /// Values sent here are read by the computer.
+    pub input: Sender<i64>,
+    /// Values written by the computer are received here.
+    pub output: Receiver<i64>,
+    /// Joining the thread returns the computer, once `run` returns.
+    pub handle: JoinHandle<Computer<ChannelIo>>,
+}
+
+/// Runs the program on a new thread, using the given `ChannelIo`. This
+/// allows computers to be wired together by sharing channel ends.
+pub fn spawn_with_io(program: Vec<i64>, io: ChannelIo) -> JoinHandle<Computer<ChannelIo>> {
+    thread::spawn(move || {
+        let mut computer = Computer::load_program(program, io);
+        computer.run();
+        computer
+    })
+}
+
+/// Runs the program on a new thread, with a fresh pair of channels
+/// for its input and output.
+pub fn spawn_computer(program: Vec<i64>) -> SpawnedComputer {
+    let (input, computer_input) = channel();
+    let (computer_output, output) = channel();
+    let handle = spawn_with_io(program, ChannelIo::new(computer_input, computer_output));
+    SpawnedComputer { input, output, handle }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Computer, ExecutionState};
+
+    #[test]
+    pub fn queue_io_reads_in_order() {
+        let mut io = QueueIo::new();
+        io.push_input(1);
+        io.extend_input(vec![2, 3]);
+        assert_eq!(io.try_read(""), Some(1));
+        assert_eq!(io.try_read(""), Some(2));
+        assert_eq!(io.try_read(""), Some(3));
+        assert_eq!(io.try_read(""), None);
+    }
+
+    #[test]
+    pub fn queue_io_collects_output() {
+        let mut io = QueueIo::new();
+        assert_eq!(io.last_output(), None);
+        io.write(10);
+        io.write(20);
+        assert_eq!(io.last_output(), Some(20));
+        assert_eq!(io.drain_output().collect::<Vec<_>>(), vec![10, 20]);
+        assert_eq!(io.last_output(), None);
+        assert!(io.output().is_empty());
+    }
+
+    #[test]
+    pub fn queue_io_makes_computer_wait_on_input() {
+        // Echoes its input until it reads a zero.
+        let program = vec![3, 11, 1006, 11, 10, 4, 11, 1105, 1, 0, 99, 0];
+        let mut computer = Computer::load_program(program, QueueIo::new());
+        assert_eq!(computer.run(), ExecutionState::WaitingOnInput);
+
+        computer.io_system.extend_input(vec![5, 6]);
+        assert_eq!(computer.run(), ExecutionState::WaitingOnInput);
+        assert_eq!(computer.io_system.drain_output().collect::<Vec<_>>(), vec![5, 6]);
+
+        computer.io_system.push_input(0);
+        assert_eq!(computer.run(), ExecutionState::Halted(3));
+        assert!(computer.io_system.output().is_empty());
+    }
+
+    #[test]
+    pub fn spawned_computer_talks_over_channels() {
+        // Doubles its input.
+        let spawned = spawn_computer(vec![3, 7, 1002, 7, 2, 7, 104, 0, 99]);
+        spawned.input.send(21).unwrap();
+        assert_eq!(spawned.output.recv().unwrap(), 42);
+        let computer = spawned.handle.join().unwrap();
+        assert_eq!(computer.execution_state, ExecutionState::Halted(3));
+    }
+
+    #[test]
+    pub fn channel_io_times_out_when_starved() {
+        let (_input, computer_input) = channel();
+        let (computer_output, _output) = channel();
+        let io = ChannelIo::new(computer_input, computer_output).with_timeout(Duration::from_millis(10));
+        let computer = spawn_with_io(vec![3, 0, 99], io).join().unwrap();
+        assert_eq!(computer.execution_state, ExecutionState::WaitingOnInput);
+    }
+
+    #[test]
+    pub fn channel_io_wires_computers_in_a_feedback_loop() {
+        // The example from day 7 part 2.
+        let program = vec![
+            3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,
+            27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5
+        ];
+        let phase_settings = [9, 8, 7, 6, 5];
+
+        // Channel i feeds amplifier i, and the last amplifier feeds the first.
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..phase_settings.len()).map(|_| channel()).unzip();
+        for (sender, &phase_setting) in senders.iter().zip(&phase_settings) {
+            sender.send(phase_setting).unwrap();
+        }
+        senders[0].send(0).unwrap();
+
+        let handles = receivers.into_iter()
+            .enumerate()
+            .map(|(i, receiver)| {
+                let output = senders[(i + 1) % senders.len()].clone();
+                spawn_with_io(program.clone(), ChannelIo::new(receiver, output))
+            })
+            .collect::<Vec<_>>();
+        drop(senders);
+
+        let computers = handles.into_iter().map(|h| h.join().unwrap()).collect::<Vec<_>>();
+        assert!(computers.iter().all(|c| c.execution_state == ExecutionState::Halted(3)));
+
+        // The final signal is left unread on the first amplifier's input.
+        assert_eq!(computers[0].io_system.drain_input(), vec![139629729]);
+    }
+}
@@ -1,6 +1,45 @@
 use bitflags::bitflags;
-
-/// The two different modes that an instruction parameter can have.
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::time::Instant;
+use compile::CompiledCode;
+use decode_cache::DecodeCache;
+use history::History;
+
+mod assembler;
+mod binary;
+mod cfg;
+mod compile;
+mod coverage;
+mod decode_cache;
+mod disassembler;
+mod history;
+mod image;
+mod io;
+mod memory;
+mod network;
+mod program;
+mod snapshot;
+mod trace;
+mod transpile;
+mod word;
+
+pub use assembler::{assemble, assemble_with_labels, Assembly, AssembleError, AssembleErrorKind};
+pub use cfg::{BasicBlock, ControlFlowGraph, Successor};
+pub use coverage::{Coverage, Hits};
+pub use disassembler::{disassemble, disassemble_from, Line};
+pub use io::{ComputerIo, StandardComputerIoSystem, QueueIo, ChannelIo, SpawnedComputer, spawn_computer, spawn_with_io};
+pub use image::{Image, WordEncoding};
+pub use memory::Memory;
+pub use network::{Network, NetworkError, NodeId};
+pub use program::{Program, ParseError, ParseErrorKind};
+pub use snapshot::MachineState;
+pub use trace::{IoEvent, JsonLinesTracer, MemoryWrite, RingBufferTracer, TraceEvent, Tracer};
+pub use transpile::transpile;
+pub use word::Word;
+
+/// The different modes that an instruction parameter can have.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum ParameterMode {
     /// The parameter is to be interpreted as a position - if the parameter is 50,
@@ -68,18 +107,56 @@ impl Instruction {
             Instruction::LessThan(..) => 4,
             Instruction::Equals(..) => 4,
             Instruction::RelativeBaseOffset(..) => 2,
-            Instruction::Halt => panic!("Do not call instruction_pointer_increment() for Halt instructions"),
+            Instruction::Halt => 1,             // Halt does not move the IP, but it occupies one word.
+        }
+    }
+
+    /// Returns the short name used for the instruction in listings.
+    pub fn mnemonic(&self) -> &'static str {
+        match *self {
+            Instruction::Add(..) => "ADD",
+            Instruction::Multiply(..) => "MUL",
+            Instruction::Read(..) => "IN",
+            Instruction::Write(..) => "OUT",
+            Instruction::JumpIfTrue(..) => "JT",
+            Instruction::JumpIfFalse(..) => "JF",
+            Instruction::LessThan(..) => "LT",
+            Instruction::Equals(..) => "EQ",
+            Instruction::RelativeBaseOffset(..) => "ARB",
+            Instruction::Halt => "HLT",
+        }
+    }
+
+    /// Returns the modes of the instruction's parameters, in order.
+    pub fn parameter_modes(&self) -> Vec<ParameterMode> {
+        match *self {
+            Instruction::Add(p1, p2, p3) => vec![p1, p2, p3],
+            Instruction::Multiply(p1, p2, p3) => vec![p1, p2, p3],
+            Instruction::Read(p1) => vec![p1],
+            Instruction::Write(p1) => vec![p1],
+            Instruction::JumpIfTrue(p1, p2) => vec![p1, p2],
+            Instruction::JumpIfFalse(p1, p2) => vec![p1, p2],
+            Instruction::LessThan(p1, p2, p3) => vec![p1, p2, p3],
+            Instruction::Equals(p1, p2, p3) => vec![p1, p2, p3],
+            Instruction::RelativeBaseOffset(p1) => vec![p1],
+            Instruction::Halt => vec![],
         }
     }
 
     /// Decodes an instruction from a raw integer.
-    pub fn decode(inst: i64) -> Result<Instruction, String> {
+    pub fn decode<W: Word>(word: W) -> Result<Instruction, DecodeError> {
         use Instruction::*;
 
+        // A word that does not even fit in an i64 is certainly out of range.
+        let inst = match word.to_i64() {
+            Some(inst) => inst,
+            None => return Err(DecodeError::OutOfRange { word: word.saturating_to_i64() }),
+        };
+
         // Include this as a sanity check so we don't start allowing
         // things like -3 to be valid instructions.
-        if inst < 1 || inst > 99_999 {
-            return Err(format!("Bad instruction {}, out of range", inst));
+        if !(1..=99_999).contains(&inst) {
+            return Err(DecodeError::OutOfRange { word: inst });
         }
 
         // The opcode is in the rightmost two digits, which we can extract
@@ -87,7 +164,7 @@ impl Instruction {
         let opcode = match inst % 100 {
             n @ 1..=9 => n,
             99 => 99,
-            _ => return Err(format!("Bad instruction {}, opcode not valid", inst))
+            n => return Err(DecodeError::InvalidOpcode { word: inst, opcode: n })
         };
 
         // Now pull out the parameter modes, being careful to *only* accept
@@ -99,9 +176,8 @@ impl Instruction {
                 let p1 = decode_parameter_mode2(inst, ParameterNumber::One, ModeFlags::ANY)?;
                 let p2 = decode_parameter_mode2(inst, ParameterNumber::Two, ModeFlags::ANY)?;
                 let p3 = decode_parameter_mode2(inst, ParameterNumber::Three, ModeFlags::POS_OR_REL)?;
-                if inst / 10000 > 0 {
-                    return Err(format!("Invalid instruction {}, superfluous digits", inst));
-                }
+                // All five digits are in use, so the range check above is
+                // enough to rule out superfluous digits.
                 Add(p1, p2, p3)
             },
 
@@ -109,24 +185,21 @@ impl Instruction {
                 let p1 = decode_parameter_mode2(inst, ParameterNumber::One, ModeFlags::ANY)?;
                 let p2 = decode_parameter_mode2(inst, ParameterNumber::Two, ModeFlags::ANY)?;
                 let p3 = decode_parameter_mode2(inst, ParameterNumber::Three, ModeFlags::POS_OR_REL)?;
-                if inst / 10000 > 0 {
-                    return Err(format!("Invalid instruction {}, superfluous digits", inst));
-                }
                 Multiply(p1, p2, p3)
             },
 
             3 => {
                 let p1 = decode_parameter_mode2(inst, ParameterNumber::One, ModeFlags::POS_OR_REL)?;
-                if inst / 100 > 0 {
-                    return Err(format!("Invalid instruction {}, superfluous digits", inst));
+                if inst / 1000 > 0 {
+                    return Err(DecodeError::SuperfluousDigits { word: inst });
                 }
                 Read(p1)
             },
 
             4 => {
                 let p1 = decode_parameter_mode2(inst, ParameterNumber::One, ModeFlags::ANY)?;
-                if inst / 100 > 2 {
-                    return Err(format!("Invalid instruction {}, superfluous digits", inst));
+                if inst / 1000 > 0 {
+                    return Err(DecodeError::SuperfluousDigits { word: inst });
                 }
                 Write(p1)
             },
@@ -135,7 +208,7 @@ impl Instruction {
                 let p1 = decode_parameter_mode2(inst, ParameterNumber::One, ModeFlags::ANY)?;
                 let p2 = decode_parameter_mode2(inst, ParameterNumber::Two, ModeFlags::ANY)?;
                 if inst / 10000 > 0 {
-                    return Err(format!("Invalid instruction {}, superfluous digits", inst));
+                    return Err(DecodeError::SuperfluousDigits { word: inst });
                 }
                 JumpIfTrue(p1, p2)
             },
@@ -144,7 +217,7 @@ impl Instruction {
                 let p1 = decode_parameter_mode2(inst, ParameterNumber::One, ModeFlags::ANY)?;
                 let p2 = decode_parameter_mode2(inst, ParameterNumber::Two, ModeFlags::ANY)?;
                 if inst / 10000 > 0 {
-                    return Err(format!("Invalid instruction {}, superfluous digits", inst));
+                    return Err(DecodeError::SuperfluousDigits { word: inst });
                 }
                 JumpIfFalse(p1, p2)
             },
@@ -153,9 +226,6 @@ impl Instruction {
                 let p1 = decode_parameter_mode2(inst, ParameterNumber::One, ModeFlags::ANY)?;
                 let p2 = decode_parameter_mode2(inst, ParameterNumber::Two, ModeFlags::ANY)?;
                 let p3 = decode_parameter_mode2(inst, ParameterNumber::Three, ModeFlags::POS_OR_REL)?;
-                if inst / 10000 > 0 {
-                    return Err(format!("Invalid instruction {}, superfluous digits", inst));
-                }
                 LessThan(p1, p2, p3)
             },
 
@@ -163,23 +233,20 @@ impl Instruction {
                 let p1 = decode_parameter_mode2(inst, ParameterNumber::One, ModeFlags::ANY)?;
                 let p2 = decode_parameter_mode2(inst, ParameterNumber::Two, ModeFlags::ANY)?;
                 let p3 = decode_parameter_mode2(inst, ParameterNumber::Three, ModeFlags::POS_OR_REL)?;
-                if inst / 10000 > 0 {
-                    return Err(format!("Invalid instruction {}, superfluous digits", inst));
-                }
                 Equals(p1, p2, p3)
             },
 
             9 => {
                 let p1 = decode_parameter_mode2(inst, ParameterNumber::One, ModeFlags::ANY)?;
-                if inst / 100 > 2 {
-                    return Err(format!("Invalid instruction {}, superfluous digits", inst));
+                if inst / 1000 > 0 {
+                    return Err(DecodeError::SuperfluousDigits { word: inst });
                 }
                 RelativeBaseOffset(p1)
             },
 
             99 => {
                 if inst / 100 > 0 {
-                    return Err(format!("Invalid instruction {}, superfluous digits", inst));
+                    return Err(DecodeError::SuperfluousDigits { word: inst });
                 }
                 Halt
             },
@@ -192,7 +259,8 @@ impl Instruction {
 }
 
 bitflags! {
-    struct ModeFlags: u32 {
+    /// The set of parameter modes that an instruction parameter accepts.
+    pub struct ModeFlags: u32 {
         const POSITION = 0b00000001;
         const IMMEDIATE = 0b00000010;
         const RELATIVE = 0b00000100;
@@ -218,7 +286,7 @@ impl ParameterNumber {
     }
 }
 
-fn decode_parameter_mode2(inst: i64, prm_num: ParameterNumber, allowed: ModeFlags) -> Result<ParameterMode, String> {
+fn decode_parameter_mode2(inst: i64, prm_num: ParameterNumber, allowed: ModeFlags) -> Result<ParameterMode, DecodeError> {
     use self::ParameterMode::*;
 
     let i = inst / match prm_num {
@@ -229,265 +297,828 @@ fn decode_parameter_mode2(inst: i64, prm_num: ParameterNumber, allowed: ModeFlag
 
     let mode = i % 10;
 
-    //println!("inst = {}, i = {}, mode = {}, prm_num = {:?}, allowed = {:?}", inst, i, mode, prm_num, allowed);
+    let (found, flag) = match mode {
+        0 => (Position, ModeFlags::POSITION),
+        1 => (Immediate, ModeFlags::IMMEDIATE),
+        2 => (Relative, ModeFlags::RELATIVE),
+        _ => return Err(DecodeError::InvalidMode { word: inst, parameter: prm_num.offset(), mode })
+    };
 
-    match mode {
-        0 => {
-            if allowed.contains(ModeFlags::POSITION) {
-                Ok(Position)
-            } else {
-                Err(format!(
-                    "In instruction {}, found parameter mode {}, which does not comply with the allowed mode {:?}",
-                    inst, mode, allowed))
-            }
-        },
-        1 => {
-            if allowed.contains(ModeFlags::IMMEDIATE) {
-                Ok(Immediate)
-            } else {
-                Err(format!(
-                    "In instruction {}, found parameter mode {}, which does not comply with the allowed mode {:?}",
-                    inst, mode, allowed))
-            }
-        },
-        2 => {
-            if allowed.contains(ModeFlags::RELATIVE) {
-                Ok(Relative)
-            } else {
-                Err(format!(
-                    "In instruction {}, found parameter mode {}, which does not comply with the allowed mode {:?}",
-                    inst, mode, allowed))
-            }
-        },
-        _ => Err(format!("In instruction {}, found invalid parameter mode {}", inst, mode))
+    if allowed.contains(flag) {
+        Ok(found)
+    } else {
+        Err(DecodeError::ModeNotAllowed { word: inst, parameter: prm_num.offset(), found, allowed })
     }
 }
 
-
-/// Represents the IO that the computer is capable of.
-pub trait ComputerIo {
-    fn try_read(&mut self, message: &str) -> Option<i64>;
-    fn write(&mut self, value: i64);
+/// The reasons that `Instruction::decode` can reject a word. Parameters
+/// are numbered from 1, in the same way as their offset from the opcode.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DecodeError {
+    /// The word is outside the range 1..=99999 that all instructions lie in.
+    OutOfRange { word: i64 },
+    /// The rightmost two digits of the word are not a known opcode.
+    InvalidOpcode { word: i64, opcode: i64 },
+    /// The mode digit for a parameter is not one of the known modes.
+    InvalidMode { word: i64, parameter: usize, mode: i64 },
+    /// The mode for a parameter is a known mode, but the instruction does
+    /// not allow it for that parameter.
+    ModeNotAllowed { word: i64, parameter: usize, found: ParameterMode, allowed: ModeFlags },
+    /// The word has mode digits for parameters that the instruction does
+    /// not have.
+    SuperfluousDigits { word: i64 },
 }
 
-/// The default implementation of `ComputerIo` reads from stdin and
-/// writes to stdout.
-pub struct StandardComputerIoSystem { }
+impl DecodeError {
+    /// Returns the word that failed to decode.
+    pub fn word(&self) -> i64 {
+        match *self {
+            DecodeError::OutOfRange { word } => word,
+            DecodeError::InvalidOpcode { word, .. } => word,
+            DecodeError::InvalidMode { word, .. } => word,
+            DecodeError::ModeNotAllowed { word, .. } => word,
+            DecodeError::SuperfluousDigits { word } => word,
+        }
+    }
+}
 
-impl StandardComputerIoSystem {
-    pub fn new() -> Self {
-        Self {}
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecodeError::OutOfRange { word } =>
+                write!(f, "Bad instruction {}, out of range", word),
+            DecodeError::InvalidOpcode { word, opcode } =>
+                write!(f, "Bad instruction {}, opcode {} not valid", word, opcode),
+            DecodeError::InvalidMode { word, parameter, mode } =>
+                write!(f, "In instruction {}, found invalid parameter mode {} for parameter {}",
+                    word, mode, parameter),
+            DecodeError::ModeNotAllowed { word, parameter, found, allowed } =>
+                write!(f, "In instruction {}, found parameter mode {:?} for parameter {}, \
+                    which does not comply with the allowed mode {:?}",
+                    word, found, parameter, allowed),
+            DecodeError::SuperfluousDigits { word } =>
+                write!(f, "Invalid instruction {}, superfluous digits", word),
+        }
     }
 }
 
-impl ComputerIo for StandardComputerIoSystem {
-    fn try_read(&mut self, message: &str) -> Option<i64> {
-        use std::io::Write;
-        use std::io::{stdout, stdin};
+impl Error for DecodeError {}
 
-        loop {
-            print!("{}", message);
-            stdout().flush().unwrap();
-            let mut ret = String::new();
-            stdin().read_line(&mut ret).expect("Failed to read from stdin");
-
-            match ret.trim().parse::<i64>() {
-                Ok(value) => return Some(value),
-                Err(_) => {
-                    println!("\nNOT A VALID INTEGER. Try again.");
-                }
-            }
+
+/// The reasons that a computer can fault. A faulted computer stops
+/// executing, rather than panicking, so that a bad program cannot take
+/// down the process that is hosting the computer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Fault {
+    /// The word at the instruction pointer does not contain a valid opcode.
+    BadOpcode,
+    /// The word at the instruction pointer contains a valid opcode, but its
+    /// parameter modes are invalid or not allowed for that opcode.
+    BadMode,
+    /// A Position or Relative mode parameter refers to the given (negative)
+    /// address.
+    NegativeAddress(i64),
+    /// The program tried to use an address beyond the memory's maximum
+    /// address.
+    AddressLimitExceeded(usize),
+    /// An instruction attempted to write to an Immediate mode parameter.
+    ImmediateWrite,
+    /// The instruction pointer was moved to the given address, which is
+    /// negative or beyond the memory's maximum address.
+    IpOutOfRange(i64),
+    /// An arithmetic operation overflowed a word.
+    ArithmeticOverflow,
+}
+
+/// What Add and Multiply instructions do when their result does not fit
+/// in a word.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum OverflowPolicy {
+    /// The computer faults with `Fault::ArithmeticOverflow`.
+    #[default]
+    Fault,
+    /// The result wraps around, two's complement style.
+    Wrapping,
+    /// The result is clamped to the largest or smallest word.
+    Saturating,
+}
+
+impl OverflowPolicy {
+    fn add<W: Word>(self, a: &W, b: &W) -> Result<W, Fault> {
+        match self {
+            OverflowPolicy::Fault => a.checked_add(b).ok_or(Fault::ArithmeticOverflow),
+            OverflowPolicy::Wrapping => Ok(a.wrapping_add(b)),
+            OverflowPolicy::Saturating => Ok(a.saturating_add(b)),
         }
     }
 
-    fn write(&mut self, value: i64) {
-        println!("{}", value);
+    fn multiply<W: Word>(self, a: &W, b: &W) -> Result<W, Fault> {
+        match self {
+            OverflowPolicy::Fault => a.checked_mul(b).ok_or(Fault::ArithmeticOverflow),
+            OverflowPolicy::Wrapping => Ok(a.wrapping_mul(b)),
+            OverflowPolicy::Saturating => Ok(a.saturating_mul(b)),
+        }
     }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub enum ExecutionState {
+pub enum ExecutionState<W = i64> {
     Running,
-    Halted(i64),
+    Halted(W),
     WaitingOnInput,
+    /// The computer faulted while executing the instruction at `ip`,
+    /// whose raw (undecoded) value was `word`.
+    Faulted { ip: usize, word: W, fault: Fault },
+    /// The computer stopped because it used up the budget it was given by
+    /// `run_with_budget` or `run_with_limits`. Running it again resumes
+    /// execution.
+    BudgetExhausted,
 }
 
-/// Represents the virtual machine we are executing the program on.
-pub struct Computer<I> {
+/// How many instructions `run_with_limits` executes between checks of the
+/// clock, as reading it is slow compared to executing an instruction.
+const DEADLINE_CHECK_INTERVAL: usize = 1024;
+
+/// Represents the virtual machine we are executing the program on. The
+/// words in its memory are `i64`s unless another `Word` type is chosen.
+pub struct Computer<I, W = i64> {
     instruction_pointer: usize,
-    program: Vec<i64>,
+    memory: Memory<W>,
     relative_base: i64,
+    /// Input supplied by `provide_input`, which is consumed before
+    /// the IO system is asked for anything.
+    pending_input: VecDeque<W>,
+    tracer: Option<Box<dyn Tracer<W> + Send>>,
+    /// The event for the instruction being executed, if we are tracing.
+    current_event: Option<TraceEvent<W>>,
+    /// The undo log, if history is enabled.
+    history: Option<History<W>>,
+    /// What the program has executed, read and written, if coverage is
+    /// enabled.
+    coverage: Option<Coverage>,
+    /// Instructions that have already been decoded, if caching is enabled.
+    decode_cache: Option<DecodeCache>,
+    /// The program's code compiled to closures, if it has been compiled.
+    compiled: Option<CompiledCode<I, W>>,
+    overflow_policy: OverflowPolicy,
     pub io_system: I,
-    pub execution_state: ExecutionState,
+    pub execution_state: ExecutionState<W>,
 }
 
-impl<I> Computer<I>
-    where I: ComputerIo
+impl<I, W> Computer<I, W>
+    where I: ComputerIo<W>,
+          W: Word
 {
-    pub fn load_program(program: Vec<i64>, io_system: I) -> Self {
+    pub fn load_program(program: Vec<W>, io_system: I) -> Self {
+        Self::load_memory(Memory::new(program), io_system)
+    }
+
+    /// Creates a computer whose memory has already been set up, for
+    /// example to limit the addresses the program can use.
+    pub fn load_memory(memory: Memory<W>, io_system: I) -> Self {
         Computer {
             instruction_pointer: 0,
-            program: program,
+            memory,
             relative_base: 0,
-            io_system: io_system,
+            pending_input: VecDeque::new(),
+            tracer: None,
+            current_event: None,
+            history: None,
+            coverage: None,
+            decode_cache: None,
+            compiled: None,
+            overflow_policy: OverflowPolicy::default(),
+            io_system,
             execution_state: ExecutionState::Running,
         }
     }
 
-    /// Executes the given program until the computer halts or has to suspend.
-    /// Returns the execution state that it reaches. If the computer halts, the
-    /// value stored in address 0 is returned, as several problems require this
-    /// as the answer.
+    /// Creates a computer holding the image's program, ready to start at
+    /// its entry point.
+    pub fn load_image(image: Image<W>, io_system: I) -> Self {
+        let mut computer = Self::load_program(image.program, io_system);
+        computer.instruction_pointer = image.entry_point.unwrap_or(0);
+        computer
+    }
+
+    /// Creates a computer in the state captured by `snapshot`.
+    pub fn from_snapshot(snapshot: &MachineState<W>, io_system: I) -> Self {
+        let mut computer = Self::load_program(Vec::new(), io_system);
+        computer.restore(snapshot);
+        computer
+    }
+
+    /// Captures the complete state of the computer, apart from its IO
+    /// system and tracer.
+    pub fn snapshot(&self) -> MachineState<W> {
+        MachineState {
+            memory: self.memory.clone(),
+            instruction_pointer: self.instruction_pointer,
+            relative_base: self.relative_base,
+            execution_state: self.execution_state.clone(),
+            pending_input: self.pending_input.iter().cloned().collect(),
+        }
+    }
+
+    /// Returns the computer to the state captured by `snapshot`. The IO
+    /// system and tracer are left alone, and any history, coverage, decoded
+    /// instructions and compiled code are forgotten.
+    pub fn restore(&mut self, snapshot: &MachineState<W>) {
+        if let Some(history) = &mut self.history {
+            *history = History::new(history.capacity());
+        }
+        if let Some(coverage) = &mut self.coverage {
+            *coverage = Coverage::new();
+        }
+        if let Some(cache) = &mut self.decode_cache {
+            cache.clear();
+        }
+        if let Some(code) = &mut self.compiled {
+            *code = CompiledCode::new();
+        }
+        self.memory = snapshot.memory.clone();
+        self.instruction_pointer = snapshot.instruction_pointer;
+        self.relative_base = snapshot.relative_base;
+        self.execution_state = snapshot.execution_state.clone();
+        self.pending_input = snapshot.pending_input.iter().cloned().collect();
+    }
+
+    /// Executes the given program until the computer halts, faults or has
+    /// to wait for input. Returns the execution state that it reaches. If the
+    /// computer halts, the value stored in address 0 is returned, as several
+    /// problems require this as the answer.
     ///
     /// If there are any problems, such as with decoding rogue instructions,
-    /// the computer panics.
-    pub fn run(&mut self) -> ExecutionState {
+    /// the computer stops in the `Faulted` state.
+    pub fn run(&mut self) -> ExecutionState<W> {
         loop {
-            self.execution_state = ExecutionState::Running;
+            // This only returns early when there is output.
+            self.execute_steps(usize::MAX);
+            if self.execution_state != ExecutionState::Running {
+                return self.execution_state.clone();
+            }
+        }
+    }
 
-            let inst = self.next_instruction().expect("Cannot decode instruction");
+    /// As `run`, but executes at most `max_instructions` instructions. If the
+    /// computer is still running after that, it stops in the
+    /// `BudgetExhausted` state and can be resumed.
+    pub fn run_with_budget(&mut self, max_instructions: usize) -> ExecutionState<W> {
+        self.run_with_limits(Some(max_instructions), None)
+    }
 
-            match inst {
-                Instruction::Add(p1, p2, p3) => {
-                    let p1_value = self.fetch_operand(ParameterNumber::One, p1);
-                    let p2_value = self.fetch_operand(ParameterNumber::Two, p2);
-                    let result = p1_value + p2_value;
-                    self.write_operand(ParameterNumber::Three, p3, result);
-                    self.instruction_pointer += inst.instruction_pointer_increment();
-                },
+    /// As `run`, but stops in the `BudgetExhausted` state if the computer
+    /// executes `max_instructions` instructions or is still running at
+    /// `deadline`, whichever comes first. Either limit can be omitted.
+    pub fn run_with_limits(&mut self, max_instructions: Option<usize>, deadline: Option<Instant>) -> ExecutionState<W> {
+        let mut executed = 0;
 
-                Instruction::Multiply(p1, p2, p3) => {
-                    let p1_value = self.fetch_operand(ParameterNumber::One, p1);
-                    let p2_value = self.fetch_operand(ParameterNumber::Two, p2);
-                    let result = p1_value * p2_value;
-                    self.write_operand(ParameterNumber::Three, p3, result);
-                    self.instruction_pointer += inst.instruction_pointer_increment();
-                },
+        loop {
+            let remaining = max_instructions.map_or(usize::MAX, |max| max - executed);
+            if remaining == 0 || deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                self.execution_state = ExecutionState::BudgetExhausted;
+                return self.execution_state.clone();
+            }
+
+            let batch = match deadline {
+                Some(_) => remaining.min(DEADLINE_CHECK_INTERVAL),
+                None => remaining,
+            };
+            executed += self.execute_steps(batch).0;
+            if self.execution_state != ExecutionState::Running {
+                return self.execution_state.clone();
+            }
+        }
+    }
+
+    /// Returns the address of the next instruction to be executed.
+    pub fn instruction_pointer(&self) -> usize {
+        self.instruction_pointer
+    }
 
-                Instruction::Read(p1) => {
-                    match self.io_system.try_read("Enter number: ") {
-                        Some(input) => {
-                            self.write_operand(ParameterNumber::One, p1, input);
-                            self.instruction_pointer += inst.instruction_pointer_increment();
+    /// Returns the current value of the Relative Base Offset register.
+    pub fn relative_base(&self) -> i64 {
+        self.relative_base
+    }
+
+    /// Returns the computer's memory.
+    pub fn memory(&self) -> &Memory<W> {
+        &self.memory
+    }
+
+    /// Returns what Add and Multiply do when they overflow.
+    pub fn overflow_policy(&self) -> OverflowPolicy {
+        self.overflow_policy
+    }
+
+    /// Sets what Add and Multiply do when they overflow. The default is to
+    /// fault. Overflow in address calculations always faults, whatever the
+    /// policy.
+    pub fn set_overflow_policy(&mut self, policy: OverflowPolicy) {
+        self.overflow_policy = policy;
+    }
+
+    /// Attaches a tracer, which will be told about every instruction
+    /// executed from now on. Replaces any existing tracer.
+    pub fn set_tracer(&mut self, tracer: Box<dyn Tracer<W> + Send>) {
+        self.tracer = Some(tracer);
+    }
+
+    /// Detaches the tracer, if there is one, and returns it.
+    pub fn take_tracer(&mut self) -> Option<Box<dyn Tracer<W> + Send>> {
+        self.tracer.take()
+    }
+
+    /// Starts recording an undo log of every instruction executed from now
+    /// on, so that they can be undone with `step_back`. Only the most recent
+    /// `capacity` instructions are remembered. Replaces any existing log.
+    pub fn enable_history(&mut self, capacity: usize) {
+        self.history = Some(History::new(capacity));
+    }
+
+    /// Stops recording the undo log and forgets everything in it.
+    pub fn disable_history(&mut self) {
+        self.history = None;
+    }
+
+    /// Returns the number of instructions that can currently be undone.
+    pub fn history_len(&self) -> usize {
+        self.history.as_ref().map_or(0, History::len)
+    }
+
+    /// Undoes the most recently executed instruction, restoring the
+    /// registers and memory to how they were before it. If it was a Read,
+    /// the value read is provided again so that re-executing the Read gives
+    /// the same result. Values that were written to the IO system cannot be
+    /// taken back.
+    ///
+    /// Returns false, and changes nothing, if there is nothing to undo.
+    pub fn step_back(&mut self) -> bool {
+        let record = match self.history.as_mut().and_then(History::pop) {
+            Some(record) => record,
+            None => return false,
+        };
+
+        for (address, old) in record.memory_writes.into_iter().rev() {
+            self.memory.set(address, old);
+            if let Some(cache) = &mut self.decode_cache {
+                cache.invalidate(address);
+            }
+            if let Some(code) = &mut self.compiled {
+                code.invalidate(address);
+            }
+        }
+        self.memory.truncate(record.memory_len);
+        if let Some(cache) = &mut self.decode_cache {
+            cache.truncate(record.memory_len);
+        }
+        self.instruction_pointer = record.instruction_pointer;
+        self.relative_base = record.relative_base;
+        if let Some(input) = record.input {
+            self.pending_input.push_front(input);
+        }
+        self.execution_state = ExecutionState::Running;
+
+        true
+    }
+
+    /// Steps back until the instruction pointer is `ip`, i.e. to just
+    /// before the last time the instruction at `ip` was executed. At least
+    /// one instruction is undone. Returns false if the history runs out
+    /// first, in which case everything in it has been undone.
+    pub fn run_back_to(&mut self, ip: usize) -> bool {
+        while self.step_back() {
+            if self.instruction_pointer == ip {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Starts recording which addresses are executed as instructions, read
+    /// and written, from now on. Replaces any existing record.
+    pub fn enable_coverage(&mut self) {
+        self.coverage = Some(Coverage::new());
+    }
+
+    /// Stops recording coverage and forgets the record.
+    pub fn disable_coverage(&mut self) {
+        self.coverage = None;
+    }
+
+    /// Returns the coverage recorded so far, if it is enabled.
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    /// Starts caching decoded instructions, so that each instruction is
+    /// only decoded the first time it is executed. This speeds up programs
+    /// that spend their time in loops. The cache is kept up to date when
+    /// the program modifies its own code.
+    pub fn enable_decode_cache(&mut self) {
+        if self.decode_cache.is_none() {
+            self.decode_cache = Some(DecodeCache::new());
+        }
+    }
+
+    /// Stops caching decoded instructions and forgets the cache.
+    pub fn disable_decode_cache(&mut self) {
+        self.decode_cache = None;
+    }
+
+    /// Compiles the code reachable from the instruction pointer into
+    /// closures, one per instruction, that are specialised for the
+    /// instruction's parameter modes. These then run instead of the
+    /// interpreter, which is much faster for programs that spend their time
+    /// in loops. Code that is only reached through computed jumps is
+    /// compiled when it is first executed.
+    ///
+    /// The computer behaves exactly as if it were interpreting. If the
+    /// program overwrites a compiled instruction, that instruction goes
+    /// back to being interpreted. The interpreter is also used while a
+    /// tracer, history or coverage is enabled.
+    pub fn compile(&mut self) {
+        let mut code = CompiledCode::new();
+        code.discover(&self.memory, self.instruction_pointer);
+        self.compiled = Some(code);
+    }
+
+    /// Forgets any compiled code, so that everything is interpreted.
+    pub fn discard_compiled_code(&mut self) {
+        self.compiled = None;
+    }
+
+    /// Returns the number of instructions that are currently compiled.
+    pub fn compiled_instructions(&self) -> usize {
+        self.compiled.as_ref().map_or(0, CompiledCode::len)
+    }
+
+    /// Supplies a value to be consumed by a subsequent Read instruction.
+    /// Values are consumed in the order they are provided, and before
+    /// anything is requested from the IO system. A computer that is
+    /// `WaitingOnInput` will retry its Read on the next call to `run`.
+    pub fn provide_input(&mut self, value: W) {
+        self.pending_input.push_back(value);
+    }
+
+    /// Executes the given program until it writes its next output, which is
+    /// returned (as well as being passed to the IO system). Returns `None` if
+    /// the computer stops for any other reason first, in which case
+    /// `execution_state` says why.
+    pub fn run_until_output(&mut self) -> Option<W> {
+        self.execute_steps(usize::MAX).1
+    }
+
+    /// Executes exactly one instruction and returns the execution state
+    /// that the computer is left in.
+    pub fn step(&mut self) -> ExecutionState<W> {
+        self.execute_steps(1);
+        self.execution_state.clone()
+    }
+
+    /// Executes instructions until `max_instructions` have been executed,
+    /// one of them is a Write, or the computer stops running. Returns the
+    /// number executed and the value written, if any.
+    fn execute_steps(&mut self, max_instructions: usize) -> (usize, Option<W>) {
+        self.execution_state = ExecutionState::Running;
+        let mut executed = 0;
+
+        while executed < max_instructions {
+            // Compiled code does not produce trace events, undo records or
+            // coverage, so it can only be used when none of them is wanted.
+            if self.tracer.is_none() && self.history.is_none() && self.coverage.is_none() {
+                if let Some(mut code) = self.compiled.take() {
+                    let (count, result) = code.run(self, max_instructions - executed);
+                    self.compiled = Some(code);
+                    executed += count;
+
+                    match result {
+                        Ok(Some(output)) => return (executed, Some(output)),
+                        Ok(None) => {},
+                        Err((ip, fault)) => {
+                            self.fault(ip, fault);
+                            return (executed, None);
                         },
-                        None => {
-                            self.execution_state = ExecutionState::WaitingOnInput;
-                            self.instruction_pointer += inst.instruction_pointer_increment();
-                            break;
-                        }
                     }
-                },
-                Instruction::Write(p1) => {
-                    let value = self.fetch_operand(ParameterNumber::One, p1);
-                    self.io_system.write(value);
-                    self.instruction_pointer += inst.instruction_pointer_increment();
-                    break;
-                },
-
-                Instruction::JumpIfTrue(p1, p2) => {
-                    let p1_value = self.fetch_operand(ParameterNumber::One, p1);
-                    if p1_value != 0 {
-                        let new_ip = self.fetch_operand(ParameterNumber::Two, p2) as usize;
-                        self.instruction_pointer = new_ip;
-                        continue;
+
+                    if executed == max_instructions || self.execution_state != ExecutionState::Running {
+                        return (executed, None);
                     }
-                    self.instruction_pointer += inst.instruction_pointer_increment();
-                },
-
-                Instruction::JumpIfFalse(p1, p2) => {
-                    let p1_value = self.fetch_operand(ParameterNumber::One, p1);
-                    if p1_value == 0 {
-                        let new_ip = self.fetch_operand(ParameterNumber::Two, p2) as usize;
-                        self.instruction_pointer = new_ip;
-                        continue;
+                }
+            }
+
+            // Either nothing is compiled or the next instruction has to be
+            // interpreted.
+            executed += 1;
+            let output = self.execute_step();
+            if output.is_some() || self.execution_state != ExecutionState::Running {
+                return (executed, output);
+            }
+        }
+
+        (executed, None)
+    }
+
+    /// Executes one instruction, moving the computer into the `Faulted`
+    /// state if it fails. Returns the value written if the instruction
+    /// was a Write.
+    fn execute_step(&mut self) -> Option<W> {
+        let ip = self.instruction_pointer;
+        if let Some(history) = &mut self.history {
+            history.begin(ip, self.relative_base, self.memory.len());
+        }
+
+        let result = self.execute_next_instruction();
+        let event = self.current_event.take();
+
+        if let Some(history) = &mut self.history {
+            // A Read that has to wait for input has not done anything yet.
+            if self.execution_state == ExecutionState::WaitingOnInput {
+                history.discard();
+            } else {
+                history.commit();
+            }
+        }
+
+        match result {
+            Ok(output) => {
+                if self.execution_state != ExecutionState::WaitingOnInput {
+                    if let Some(coverage) = &mut self.coverage {
+                        coverage.record_execute(ip);
                     }
-                    self.instruction_pointer += inst.instruction_pointer_increment();
-                },
+                    if let (Some(tracer), Some(event)) = (&mut self.tracer, event) {
+                        tracer.trace(&event);
+                    }
+                }
+                output
+            },
+            Err(fault) => {
+                self.fault(ip, fault);
+                None
+            }
+        }
+    }
 
-                Instruction::LessThan(p1, p2, p3) => {
-                    let p1_value = self.fetch_operand(ParameterNumber::One, p1);
-                    let p2_value = self.fetch_operand(ParameterNumber::Two, p2);
-                    let result = if p1_value < p2_value { 1 } else { 0 };
-                    self.write_operand(ParameterNumber::Three, p3, result);
-                    self.instruction_pointer += inst.instruction_pointer_increment();
-                },
+    /// Moves the computer into the `Faulted` state, for the instruction
+    /// at `ip`.
+    fn fault(&mut self, ip: usize, fault: Fault) {
+        let word = self.memory[ip].clone();
+        self.execution_state = ExecutionState::Faulted { ip, word: word.clone(), fault };
+        if let Some(tracer) = &mut self.tracer {
+            tracer.fault(ip, word, fault);
+        }
+    }
+
+    /// Decodes and executes the instruction at the instruction pointer.
+    /// Returns the value written if the instruction was a Write. The
+    /// execution state is updated if the instruction halts the computer
+    /// or has to wait for input.
+    fn execute_next_instruction(&mut self) -> Result<Option<W>, Fault> {
+        let inst = self.next_instruction()?;
+
+        if self.tracer.is_some() {
+            let start = self.instruction_pointer + 1;
+            let parameters = (start..start + inst.parameter_modes().len())
+                .map(|address| self.memory.get(address))
+                .collect();
+            self.current_event = Some(TraceEvent::new(self.instruction_pointer, inst, parameters));
+        }
+
+        match inst {
+            Instruction::Add(p1, p2, p3) => {
+                let p1_value = self.fetch_operand(ParameterNumber::One, p1)?;
+                let p2_value = self.fetch_operand(ParameterNumber::Two, p2)?;
+                let result = self.overflow_policy.add(&p1_value, &p2_value)?;
+                self.write_operand(ParameterNumber::Three, p3, result)?;
+                self.instruction_pointer += inst.instruction_pointer_increment();
+            },
+
+            Instruction::Multiply(p1, p2, p3) => {
+                let p1_value = self.fetch_operand(ParameterNumber::One, p1)?;
+                let p2_value = self.fetch_operand(ParameterNumber::Two, p2)?;
+                let result = self.overflow_policy.multiply(&p1_value, &p2_value)?;
+                self.write_operand(ParameterNumber::Three, p3, result)?;
+                self.instruction_pointer += inst.instruction_pointer_increment();
+            },
+
+            Instruction::Read(p1) => {
+                let input = match self.pending_input.pop_front() {
+                    Some(input) => Some(input),
+                    None => self.io_system.try_read("Enter number: "),
+                };
+
+                match input {
+                    Some(input) => {
+                        self.trace_io(IoEvent::Read(input.clone()));
+                        if let Some(history) = &mut self.history {
+                            history.record_input(input.clone());
+                        }
+                        self.write_operand(ParameterNumber::One, p1, input)?;
+                        self.instruction_pointer += inst.instruction_pointer_increment();
+                    },
+                    None => {
+                        // Leave the instruction pointer on the Read so that
+                        // it is retried when the computer is next run.
+                        self.execution_state = ExecutionState::WaitingOnInput;
+                    }
+                }
+            },
+
+            Instruction::Write(p1) => {
+                let value = self.fetch_operand(ParameterNumber::One, p1)?;
+                self.trace_io(IoEvent::Write(value.clone()));
+                self.io_system.write(value.clone());
+                self.instruction_pointer += inst.instruction_pointer_increment();
+                return Ok(Some(value));
+            },
 
-                Instruction::Equals(p1, p2, p3) => {
-                    let p1_value = self.fetch_operand(ParameterNumber::One, p1);
-                    let p2_value = self.fetch_operand(ParameterNumber::Two, p2);
-                    let result = if p1_value == p2_value { 1 } else { 0 };
-                    self.write_operand(ParameterNumber::Three, p3, result);
+            Instruction::JumpIfTrue(p1, p2) => {
+                let p1_value = self.fetch_operand(ParameterNumber::One, p1)?;
+                if !p1_value.is_zero() {
+                    let new_ip = self.fetch_operand(ParameterNumber::Two, p2)?;
+                    self.jump_to(&new_ip)?;
+                } else {
                     self.instruction_pointer += inst.instruction_pointer_increment();
-                },
+                }
+            },
 
-                Instruction::RelativeBaseOffset(p1) => {
-                    let p1_value = self.fetch_operand(ParameterNumber::One, p1);
-                    self.relative_base += p1_value;
+            Instruction::JumpIfFalse(p1, p2) => {
+                let p1_value = self.fetch_operand(ParameterNumber::One, p1)?;
+                if p1_value.is_zero() {
+                    let new_ip = self.fetch_operand(ParameterNumber::Two, p2)?;
+                    self.jump_to(&new_ip)?;
+                } else {
                     self.instruction_pointer += inst.instruction_pointer_increment();
-                },
+                }
+            },
 
-                Instruction::Halt => {
-                    self.execution_state = ExecutionState::Halted(self.program[0]);
-                    break;
-                },
-            }
+            Instruction::LessThan(p1, p2, p3) => {
+                let p1_value = self.fetch_operand(ParameterNumber::One, p1)?;
+                let p2_value = self.fetch_operand(ParameterNumber::Two, p2)?;
+                let result = if p1_value < p2_value { W::one() } else { W::zero() };
+                self.write_operand(ParameterNumber::Three, p3, result)?;
+                self.instruction_pointer += inst.instruction_pointer_increment();
+            },
+
+            Instruction::Equals(p1, p2, p3) => {
+                let p1_value = self.fetch_operand(ParameterNumber::One, p1)?;
+                let p2_value = self.fetch_operand(ParameterNumber::Two, p2)?;
+                let result = if p1_value == p2_value { W::one() } else { W::zero() };
+                self.write_operand(ParameterNumber::Three, p3, result)?;
+                self.instruction_pointer += inst.instruction_pointer_increment();
+            },
+
+            Instruction::RelativeBaseOffset(p1) => {
+                let p1_value = self.fetch_operand(ParameterNumber::One, p1)?;
+                let offset = p1_value.to_i64().ok_or(Fault::ArithmeticOverflow)?;
+                let old_base = self.relative_base;
+                self.relative_base = self.relative_base.checked_add(offset).ok_or(Fault::ArithmeticOverflow)?;
+                if let Some(event) = &mut self.current_event {
+                    event.relative_base_change = Some((old_base, self.relative_base));
+                }
+                self.instruction_pointer += inst.instruction_pointer_increment();
+            },
+
+            Instruction::Halt => {
+                self.execution_state = ExecutionState::Halted(self.memory[0].clone());
+            },
         }
 
-        self.execution_state
+        Ok(None)
     }
 
-    fn next_instruction(&self) -> Result<Instruction, String> {
-        Instruction::decode(self.program[self.instruction_pointer])
+    fn next_instruction(&mut self) -> Result<Instruction, Fault> {
+        let ip = self.instruction_pointer;
+        if let Some(inst) = self.decode_cache.as_ref().and_then(|cache| cache.get(ip)) {
+            return Ok(inst);
+        }
+
+        // Any address the program can write to can be executed, including
+        // ones in the sparse part of memory. Unwritten ones read as 0,
+        // which is not a valid instruction.
+        if ip > self.memory.max_address() {
+            return Err(Fault::IpOutOfRange(ip as i64));
+        }
+        let word = self.memory.get(ip);
+
+        let inst = Instruction::decode(word).map_err(|err| match err {
+            DecodeError::OutOfRange { .. } | DecodeError::InvalidOpcode { .. } => Fault::BadOpcode,
+            DecodeError::InvalidMode { .. }
+                | DecodeError::ModeNotAllowed { .. }
+                | DecodeError::SuperfluousDigits { .. } => Fault::BadMode,
+        })?;
+
+        if let Some(cache) = &mut self.decode_cache {
+            cache.insert(ip, inst);
+        }
+        Ok(inst)
     }
 
-    fn fetch_operand(&mut self, operand_number: ParameterNumber, mode: ParameterMode) -> i64 {
+    fn trace_io(&mut self, io: IoEvent<W>) {
+        if let Some(event) = &mut self.current_event {
+            event.io = Some(io);
+        }
+    }
+
+    fn jump_to(&mut self, new_ip: &W) -> Result<(), Fault> {
+        match new_ip.to_i64() {
+            Some(ip) if ip >= 0 && (ip as usize) <= self.memory.max_address() => {
+                self.instruction_pointer = ip as usize;
+                Ok(())
+            },
+            _ => Err(Fault::IpOutOfRange(new_ip.saturating_to_i64())),
+        }
+    }
+
+    fn fetch_operand(&mut self, operand_number: ParameterNumber, mode: ParameterMode) -> Result<W, Fault> {
         let offset = operand_number.offset();
         let operand_index = self.instruction_pointer + offset;
-        self.grow_memory_if_needed(operand_index);
 
-        match mode {
-            ParameterMode::Position => {
-                let address = self.program[operand_index];
-                if address < 0 {
-                    panic!("SIGSEGV: address = {}", address);
+        let value = match mode {
+            ParameterMode::Position | ParameterMode::Relative => {
+                let address = self.operand_address(operand_index, mode)?;
+                if let Some(coverage) = &mut self.coverage {
+                    coverage.record_read(address);
                 }
-                self.grow_memory_if_needed(address as usize);
-                self.program[address as usize]
-            },
-            ParameterMode::Immediate => self.program[operand_index],
-            ParameterMode::Relative => {
-                unimplemented!();
+                self.memory[address].clone()
             },
+            ParameterMode::Immediate => self.memory[operand_index].clone(),
+        };
+
+        if let Some(event) = &mut self.current_event {
+            event.operands.push(value.clone());
         }
+
+        Ok(value)
     }
 
-    fn write_operand(&mut self, operand_number: ParameterNumber, mode: ParameterMode, value: i64) {
+    fn write_operand(&mut self, operand_number: ParameterNumber, mode: ParameterMode, value: W) -> Result<(), Fault> {
         let offset = operand_number.offset();
         let operand_index = self.instruction_pointer + offset;
-        self.grow_memory_if_needed(operand_index);
 
         match mode {
-            ParameterMode::Position => {
-                let address = self.program[operand_index];
-                if address < 0 {
-                    panic!("SIGSEGV: address = {}", address);
+            ParameterMode::Position | ParameterMode::Relative => {
+                let address = self.operand_address(operand_index, mode)?;
+                let old = self.memory[address].clone();
+                if let Some(event) = &mut self.current_event {
+                    event.memory_writes.push(MemoryWrite { address, old: old.clone(), new: value.clone() });
                 }
-                self.grow_memory_if_needed(address as usize);
-                self.program[address as usize] = value;
-
+                if let Some(history) = &mut self.history {
+                    history.record_write(address, old);
+                }
+                if let Some(coverage) = &mut self.coverage {
+                    coverage.record_write(address);
+                }
+                if let Some(cache) = &mut self.decode_cache {
+                    cache.invalidate(address);
+                }
+                if let Some(code) = &mut self.compiled {
+                    code.invalidate(address);
+                }
+                self.memory.set(address, value);
+                Ok(())
             },
-            ParameterMode::Immediate => panic!("FAULT: Cannot write to Immediate mode parameter"),
+            ParameterMode::Immediate => Err(Fault::ImmediateWrite),
+        }
+    }
+
+    /// Calculates the address that a Position or Relative mode parameter
+    /// stored at `operand_index` refers to.
+    fn operand_address(&self, operand_index: usize, mode: ParameterMode) -> Result<usize, Fault> {
+        let parameter = &self.memory[operand_index];
+        let parameter = match parameter.to_i64() {
+            Some(parameter) => parameter,
+            None if parameter.saturating_to_i64() < 0 => return Err(Fault::NegativeAddress(i64::MIN)),
+            None => return Err(Fault::AddressLimitExceeded(usize::MAX)),
+        };
+
+        let address = match mode {
+            ParameterMode::Position => parameter,
             ParameterMode::Relative => {
-                unimplemented!();
+                self.relative_base.checked_add(parameter)
+                    .ok_or(Fault::ArithmeticOverflow)?
             },
-        }
+            ParameterMode::Immediate => unreachable!("Immediate mode parameters do not refer to an address"),
+        };
+
+        check_address(&self.memory, address)
     }
+}
 
-    fn grow_memory_if_needed(&mut self, address: usize) {
-        if address >= self.program.len() {
-            self.program.resize(address + 1, 0);
-        }
+/// Checks that the program is allowed to use `address`, returning it as
+/// an index into memory if so.
+fn check_address<W: Word>(memory: &Memory<W>, address: i64) -> Result<usize, Fault> {
+    if address < 0 {
+        return Err(Fault::NegativeAddress(address));
+    }
+
+    let address = address as usize;
+    if address > memory.max_address() {
+        return Err(Fault::AddressLimitExceeded(address));
     }
+
+    Ok(address)
 }
 
 #[cfg(test)]
@@ -539,6 +1170,7 @@ mod tests {
     pub fn decode_read() {
         assert_eq!(Instruction::decode(3).unwrap(), Read(Position));
         assert!(Instruction::decode(103).is_err(), "Write prm must be Position mode");
+        assert!(Instruction::decode(1003).is_err(), "Too many digits");
     }
 
     #[test]
@@ -579,4 +1211,248 @@ mod tests {
         assert_eq!(Instruction::decode(1008).unwrap(), Equals(Position, Immediate, Position));
         assert!(Instruction::decode(10008).is_err(), "Write prm must be Position mode");
     }
+
+    #[test]
+    pub fn decode_relative_modes() {
+        assert_eq!(Instruction::decode(22201).unwrap(), Add(Relative, Relative, Relative));
+        assert_eq!(Instruction::decode(203).unwrap(), Read(Relative));
+        assert_eq!(Instruction::decode(204).unwrap(), Write(Relative));
+        assert_eq!(Instruction::decode(2205).unwrap(), JumpIfTrue(Relative, Relative));
+        assert_eq!(Instruction::decode(21101).unwrap(), Add(Immediate, Immediate, Relative));
+        assert_eq!(Instruction::decode(109).unwrap(), RelativeBaseOffset(Immediate));
+        assert_eq!(Instruction::decode(209).unwrap(), RelativeBaseOffset(Relative));
+        assert!(Instruction::decode(1009).is_err(), "Too many digits");
+    }
+
+    #[test]
+    pub fn decode_errors_are_classified() {
+        assert_eq!(Instruction::decode(0), Err(DecodeError::OutOfRange { word: 0 }));
+        assert_eq!(Instruction::decode(100_000), Err(DecodeError::OutOfRange { word: 100_000 }));
+        assert_eq!(Instruction::decode(98), Err(DecodeError::InvalidOpcode { word: 98, opcode: 98 }));
+        assert_eq!(Instruction::decode(199), Err(DecodeError::SuperfluousDigits { word: 199 }));
+        assert_eq!(Instruction::decode(3001),
+            Err(DecodeError::InvalidMode { word: 3001, parameter: 2, mode: 3 }));
+        assert_eq!(Instruction::decode(10001),
+            Err(DecodeError::ModeNotAllowed {
+                word: 10001, parameter: 3, found: Immediate, allowed: ModeFlags::POS_OR_REL
+            }));
+        assert_eq!(Instruction::decode(103).unwrap_err().word(), 103);
+    }
+
+    #[test]
+    pub fn decode_error_messages() {
+        assert_eq!(Instruction::decode(98).unwrap_err().to_string(),
+            "Bad instruction 98, opcode 98 not valid");
+        assert_eq!(Instruction::decode(103).unwrap_err().to_string(),
+            "In instruction 103, found parameter mode Immediate for parameter 1, \
+            which does not comply with the allowed mode POSITION | RELATIVE | POS_OR_REL");
+    }
+
+    fn test_io(inputs: Vec<i64>) -> QueueIo {
+        let mut io = QueueIo::new();
+        io.extend_input(inputs);
+        io
+    }
+
+    fn run_to_halt(program: Vec<i64>, inputs: Vec<i64>) -> Computer<QueueIo> {
+        let mut computer = Computer::load_program(program, test_io(inputs));
+        computer.run();
+        computer
+    }
+
+    #[test]
+    pub fn run_relative_mode_read_and_write() {
+        // Set the relative base to 10, read into rb+2 (address 12), then
+        // double it into rb+3 (address 13) and output that.
+        let program = vec![109, 10, 203, 2, 21202, 2, 2, 3, 204, 3, 99];
+        let computer = run_to_halt(program, vec![21]);
+        assert_eq!(computer.io_system.output(), &[42]);
+        assert_eq!(computer.memory[12], 21);
+        assert_eq!(computer.memory[13], 42);
+    }
+
+    #[test]
+    pub fn run_relative_mode_jump_target() {
+        // rb = 3, then jump to the address stored at rb+4 (address 7), which is 8.
+        let program = vec![109, 3, 2105, 1, 4, 99, 99, 8, 104, 7, 99];
+        let computer = run_to_halt(program, vec![]);
+        assert_eq!(computer.io_system.output(), &[7]);
+    }
+
+    fn assert_faults(program: Vec<i64>, expected_ip: usize, expected_fault: Fault) {
+        let computer = run_to_halt(program.clone(), vec![]);
+        match computer.execution_state {
+            ExecutionState::Faulted { ip, word, fault } => {
+                assert_eq!(ip, expected_ip);
+                assert_eq!(word, program.get(ip).copied().unwrap_or(0));
+                assert_eq!(fault, expected_fault);
+            },
+            state => panic!("Expected a fault, got {:?}", state),
+        }
+    }
+
+    #[test]
+    pub fn run_faults_on_bad_opcode() {
+        assert_faults(vec![1101, 1, 1, 5, 98, 0], 4, Fault::BadOpcode);
+        assert_faults(vec![-1], 0, Fault::BadOpcode);
+    }
+
+    #[test]
+    pub fn run_faults_on_bad_mode() {
+        assert_faults(vec![104, 7, 10001, 0, 0, 0, 99], 2, Fault::BadMode);
+        assert_faults(vec![399, 0], 0, Fault::BadMode);
+    }
+
+    #[test]
+    pub fn run_faults_on_negative_address() {
+        assert_faults(vec![1, -3, 0, 0, 99], 0, Fault::NegativeAddress(-3));
+        assert_faults(vec![109, -10, 204, 2, 99], 2, Fault::NegativeAddress(-8));
+    }
+
+    #[test]
+    pub fn run_faults_when_ip_leaves_memory() {
+        assert_faults(vec![1105, 1, -4, 99], 0, Fault::IpOutOfRange(-4));
+
+        let mut memory = Memory::new(vec![1105, 1, 1000, 99]);
+        memory.set_max_address(999);
+        let mut computer = Computer::load_memory(memory, QueueIo::new());
+        assert_eq!(computer.run(), ExecutionState::Faulted { ip: 0, word: 1105, fault: Fault::IpOutOfRange(1000) });
+    }
+
+    #[test]
+    pub fn unwritten_memory_executes_as_a_bad_opcode() {
+        assert_faults(vec![1105, 1, 1000, 99], 1000, Fault::BadOpcode);
+        assert_faults(vec![1101, 1, 1, 0], 4, Fault::BadOpcode);
+    }
+
+    #[test]
+    pub fn sparse_memory_is_executable() {
+        // Writes a Halt far beyond the program and jumps to it.
+        let program = vec![1101, 99, 0, 100_000, 1105, 1, 100_000];
+        let mut memory = Memory::with_dense_limit(program, 1000);
+        let mut computer = Computer::load_memory(memory.clone(), QueueIo::new());
+        assert_eq!(computer.run(), ExecutionState::Halted(1101));
+        assert_eq!(computer.memory().len(), 7);
+
+        // And so is the dense part, beyond the program.
+        memory = Memory::new(vec![1101, 99, 0, 100, 1105, 1, 100]);
+        let mut computer = Computer::load_memory(memory, QueueIo::new());
+        assert_eq!(computer.run(), ExecutionState::Halted(1101));
+    }
+
+    #[test]
+    pub fn run_faults_on_arithmetic_overflow() {
+        assert_faults(vec![1101, i64::MAX, 1, 0, 99], 0, Fault::ArithmeticOverflow);
+        assert_faults(vec![1102, i64::MAX, 2, 0, 99], 0, Fault::ArithmeticOverflow);
+        assert_faults(vec![109, i64::MAX, 109, 1, 99], 2, Fault::ArithmeticOverflow);
+    }
+
+    #[test]
+    pub fn overflow_policy_controls_add_and_multiply() {
+        let run_with_policy = |program: Vec<i64>, policy| {
+            let mut computer = Computer::load_program(program, test_io(vec![]));
+            computer.set_overflow_policy(policy);
+            computer.run()
+        };
+
+        assert_eq!(run_with_policy(vec![1101, i64::MAX, 1, 0, 99], OverflowPolicy::Wrapping), ExecutionState::Halted(i64::MIN));
+        assert_eq!(run_with_policy(vec![1101, i64::MAX, 1, 0, 99], OverflowPolicy::Saturating), ExecutionState::Halted(i64::MAX));
+        assert_eq!(run_with_policy(vec![1102, i64::MIN, 2, 0, 99], OverflowPolicy::Wrapping), ExecutionState::Halted(0));
+        assert_eq!(run_with_policy(vec![1102, i64::MIN, 2, 0, 99], OverflowPolicy::Saturating), ExecutionState::Halted(i64::MIN));
+        assert!(matches!(
+            run_with_policy(vec![1102, i64::MIN, 2, 0, 99], OverflowPolicy::Fault),
+            ExecutionState::Faulted { fault: Fault::ArithmeticOverflow, .. }
+        ));
+
+        // Address calculations fault whatever the policy.
+        assert!(matches!(
+            run_with_policy(vec![109, i64::MAX, 109, 1, 99], OverflowPolicy::Wrapping),
+            ExecutionState::Faulted { fault: Fault::ArithmeticOverflow, .. }
+        ));
+    }
+
+    #[test]
+    pub fn run_does_not_suspend_after_write() {
+        let mut computer = Computer::load_program(vec![104, 1, 104, 2, 99], test_io(vec![]));
+        assert_eq!(computer.run(), ExecutionState::Halted(104));
+        assert_eq!(computer.io_system.output(), &[1, 2]);
+    }
+
+    #[test]
+    pub fn run_until_output_returns_each_output() {
+        let mut computer = Computer::load_program(vec![104, 1, 104, 2, 99], test_io(vec![]));
+        assert_eq!(computer.run_until_output(), Some(1));
+        assert_eq!(computer.execution_state, ExecutionState::Running);
+        assert_eq!(computer.run_until_output(), Some(2));
+        assert_eq!(computer.run_until_output(), None);
+        assert_eq!(computer.execution_state, ExecutionState::Halted(104));
+    }
+
+    #[test]
+    pub fn run_with_budget_stops_runaway_programs() {
+        // Loops forever, counting in address 7.
+        let program = vec![101, 1, 7, 7, 1105, 1, 0, 0];
+        let mut computer = Computer::load_program(program, test_io(vec![]));
+        assert_eq!(computer.run_with_budget(10), ExecutionState::BudgetExhausted);
+        assert_eq!(computer.memory()[7], 5);
+
+        // It can be resumed from where it stopped.
+        assert_eq!(computer.run_with_budget(4), ExecutionState::BudgetExhausted);
+        assert_eq!(computer.memory()[7], 7);
+    }
+
+    #[test]
+    pub fn run_with_budget_finishes_within_budget() {
+        let mut computer = Computer::load_program(vec![104, 5, 99], test_io(vec![]));
+        assert_eq!(computer.run_with_budget(2), ExecutionState::Halted(104));
+
+        let mut computer = Computer::load_program(vec![3, 0, 99], test_io(vec![]));
+        assert_eq!(computer.run_with_budget(0), ExecutionState::BudgetExhausted);
+        assert_eq!(computer.run_with_budget(100), ExecutionState::WaitingOnInput);
+    }
+
+    #[test]
+    pub fn run_with_limits_stops_at_the_deadline() {
+        let program = vec![1105, 1, 0];
+        let mut computer = Computer::load_program(program, test_io(vec![]));
+        let deadline = Instant::now() + std::time::Duration::from_millis(20);
+        assert_eq!(computer.run_with_limits(None, Some(deadline)), ExecutionState::BudgetExhausted);
+        assert!(Instant::now() >= deadline);
+    }
+
+    #[test]
+    pub fn step_executes_one_instruction() {
+        let mut computer = Computer::load_program(vec![1101, 2, 3, 0, 104, 0, 99], test_io(vec![]));
+        assert_eq!(computer.step(), ExecutionState::Running);
+        assert_eq!(computer.instruction_pointer, 4);
+        assert_eq!(computer.memory[0], 5);
+        assert!(computer.io_system.output().is_empty());
+
+        assert_eq!(computer.step(), ExecutionState::Running);
+        assert_eq!(computer.io_system.output(), &[0]);
+        assert_eq!(computer.step(), ExecutionState::Halted(5));
+    }
+
+    #[test]
+    pub fn read_is_retried_after_waiting_on_input() {
+        // Reads two numbers and outputs their sum.
+        let program = vec![3, 13, 3, 14, 1, 13, 14, 15, 4, 15, 99, 0, 0, 0, 0, 0];
+        let mut computer = Computer::load_program(program, test_io(vec![]));
+        computer.provide_input(2);
+        assert_eq!(computer.run(), ExecutionState::WaitingOnInput);
+        assert_eq!(computer.instruction_pointer, 2, "The blocked Read should not be skipped");
+
+        computer.provide_input(3);
+        assert_eq!(computer.run(), ExecutionState::Halted(3));
+        assert_eq!(computer.io_system.output(), &[5]);
+    }
+
+    #[test]
+    pub fn provided_input_is_consumed_before_io_system() {
+        let program = vec![3, 9, 3, 10, 4, 9, 4, 10, 99, 0, 0];
+        let mut computer = Computer::load_program(program, test_io(vec![20]));
+        computer.provide_input(10);
+        assert_eq!(computer.run(), ExecutionState::Halted(3));
+        assert_eq!(computer.io_system.output(), &[10, 20]);
+    }
 }
@@ -0,0 +1,202 @@
+use std::collections::BTreeMap;
+use std::ops::Index;
+use crate::Word;
+
+/// The number of words in each page of the sparse store.
+const PAGE_SIZE: usize = 256;
+
+/// Addresses below this are always stored densely, however little of the
+/// space is used, as it is cheap enough not to be worth worrying about.
+const DEFAULT_DENSE_LIMIT: usize = 1 << 16;
+
+/// The memory of a computer.
+///
+/// The loaded program, and anything written just past it, is kept in a
+/// dense `Vec`. Writes to addresses at or beyond the dense limit go to a
+/// store of fixed size pages instead, so that a program writing to a huge
+/// address only costs one page. Addresses that have never been written
+/// read as 0.
+///
+/// Addresses beyond the maximum address cannot be used at all; the
+/// computer faults with `Fault::AddressLimitExceeded` if the program tries.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Memory<W = i64> {
+    dense: Vec<W>,
+    /// Pages of the sparse store, keyed by the address of their first word.
+    pages: BTreeMap<usize, Vec<W>>,
+    dense_limit: usize,
+    max_address: usize,
+    /// What unwritten addresses read as.
+    zero: W,
+}
+
+impl<W: Word> Memory<W> {
+    /// Creates a memory holding the program, with the default limits: the
+    /// first 64K words (or the size of the program, if bigger) are dense,
+    /// and there is no maximum address.
+    pub fn new(program: Vec<W>) -> Self {
+        Self::with_dense_limit(program, DEFAULT_DENSE_LIMIT)
+    }
+
+    /// Creates a memory holding the program, where addresses below
+    /// `dense_limit` (or the size of the program, if bigger) are dense.
+    pub fn with_dense_limit(program: Vec<W>, dense_limit: usize) -> Self {
+        Self {
+            dense_limit: program.len().max(dense_limit),
+            dense: program,
+            pages: BTreeMap::new(),
+            max_address: usize::MAX,
+            zero: W::zero(),
+        }
+    }
+
+    /// Returns the value at `address`.
+    pub fn get(&self, address: usize) -> W {
+        self[address].clone()
+    }
+
+    /// Stores a value at `address`.
+    ///
+    /// # Panics
+    ///
+    /// If `address` is beyond the maximum address. The computer checks
+    /// addresses before writing, and faults instead.
+    pub fn set(&mut self, address: usize, value: W) {
+        assert!(address <= self.max_address, "Address {} is beyond the limit", address);
+
+        if address < self.dense.len() {
+            self.dense[address] = value;
+        } else if address < self.dense_limit {
+            self.dense.resize(address + 1, W::zero());
+            self.dense[address] = value;
+        } else {
+            let page_start = address - address % PAGE_SIZE;
+            let page = self.pages.entry(page_start).or_insert_with(|| vec![W::zero(); PAGE_SIZE]);
+            page[address - page_start] = value;
+        }
+    }
+
+    /// Returns the dense part of memory, which starts with the loaded
+    /// program. This is where the program's code lives.
+    pub fn dense(&self) -> &[W] {
+        &self.dense
+    }
+
+    /// Returns the length of the dense part of memory.
+    pub fn len(&self) -> usize {
+        self.dense.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dense.is_empty()
+    }
+
+    /// Returns the address and value of every non-zero word in the sparse
+    /// part of memory, in address order.
+    pub fn sparse_words(&self) -> impl Iterator<Item = (usize, W)> + '_ {
+        self.pages.iter().flat_map(|(&start, page)| {
+            page.iter().enumerate()
+                .filter(|&(_, value)| !value.is_zero())
+                .map(move |(offset, value)| (start + offset, value.clone()))
+        })
+    }
+
+    /// Returns the address below which memory is stored densely.
+    pub fn dense_limit(&self) -> usize {
+        self.dense_limit
+    }
+
+    /// Returns the highest address that the program may use.
+    pub fn max_address(&self) -> usize {
+        self.max_address
+    }
+
+    /// Sets the highest address that the program may use.
+    pub fn set_max_address(&mut self, max_address: usize) {
+        self.max_address = max_address;
+    }
+
+    /// Shrinks the dense part of memory back to `len` words, if it has
+    /// grown beyond that.
+    pub(crate) fn truncate(&mut self, len: usize) {
+        self.dense.truncate(len);
+    }
+}
+
+impl<W: Word> Index<usize> for Memory<W> {
+    type Output = W;
+
+    fn index(&self, address: usize) -> &W {
+        if let Some(value) = self.dense.get(address) {
+            return value;
+        }
+
+        let page_start = address - address % PAGE_SIZE;
+        match self.pages.get(&page_start) {
+            Some(page) => &page[address - page_start],
+            None => &self.zero,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Computer, ExecutionState, Fault, QueueIo};
+
+    #[test]
+    pub fn unwritten_addresses_read_as_zero() {
+        let memory = Memory::new(vec![1, 2, 3]);
+        assert_eq!(memory[2], 3);
+        assert_eq!(memory[3], 0);
+        assert_eq!(memory.get(1_000_000_000_000), 0);
+        assert_eq!(memory.len(), 3);
+    }
+
+    #[test]
+    pub fn far_writes_are_sparse() {
+        let mut memory = Memory::new(vec![1, 2, 3]);
+        memory.set(10, 4);
+        memory.set(1_000_000_000_000, 5);
+        memory.set(1_000_000_000_001, 6);
+
+        assert_eq!(memory.dense(), &[1, 2, 3, 0, 0, 0, 0, 0, 0, 0, 4]);
+        assert_eq!(memory[1_000_000_000_000], 5);
+        assert_eq!(memory.sparse_words().collect::<Vec<_>>(), vec![(1_000_000_000_000, 5), (1_000_000_000_001, 6)]);
+    }
+
+    #[test]
+    pub fn dense_limit_is_configurable() {
+        let mut memory = Memory::with_dense_limit(vec![1, 2, 3], 5);
+        memory.set(4, 7);
+        memory.set(5, 8);
+        assert_eq!(memory.dense(), &[1, 2, 3, 0, 7]);
+        assert_eq!(memory.sparse_words().collect::<Vec<_>>(), vec![(5, 8)]);
+    }
+
+    #[test]
+    pub fn computer_can_write_to_huge_addresses() {
+        // Writes to address 10^12 and reads it back.
+        let program: Vec<i64> = vec![1101, 20, 22, 1_000_000_000_000, 4, 1_000_000_000_000, 99];
+        let mut computer = Computer::load_program(program, QueueIo::new());
+        assert_eq!(computer.run(), ExecutionState::Halted(1101));
+        assert_eq!(computer.io_system.output(), &[42]);
+        assert_eq!(computer.memory().len(), 7);
+    }
+
+    #[test]
+    pub fn computer_faults_beyond_the_max_address() {
+        let program = vec![1101, 20, 22, 1000, 99];
+        let mut memory = Memory::new(program);
+        memory.set_max_address(999);
+        let mut computer = Computer::load_memory(memory, QueueIo::new());
+        let state = computer.run();
+        assert_eq!(state, ExecutionState::Faulted { ip: 0, word: 1101, fault: Fault::AddressLimitExceeded(1000) });
+
+        // Reading is not allowed either.
+        let mut memory = Memory::new(vec![4, 1000, 99]);
+        memory.set_max_address(999);
+        let mut computer = Computer::load_memory(memory, QueueIo::new());
+        assert!(matches!(computer.run(), ExecutionState::Faulted { fault: Fault::AddressLimitExceeded(1000), .. }));
+    }
+}
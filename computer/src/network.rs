@@ -0,0 +1,200 @@
+use std::error::Error;
+use std::fmt;
+use crate::{Computer, ExecutionState, QueueIo};
+
+/// Identifies a node (computer) within a `Network`.
+pub type NodeId = usize;
+
+/// The reasons that a `Network` can fail to run to completion.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum NetworkError {
+    /// The node stopped in the given (faulted) state.
+    Faulted { node: NodeId, state: ExecutionState },
+    /// Every node that has not halted is waiting on input that can
+    /// never arrive.
+    Deadlock,
+}
+
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            NetworkError::Faulted { node, state } => write!(f, "Node {} faulted: {:?}", node, state),
+            NetworkError::Deadlock => write!(f, "All running nodes are waiting on input"),
+        }
+    }
+}
+
+impl Error for NetworkError {}
+
+/// A set of computers wired together so that the output of one becomes
+/// the input of others, as with day 7's amplifiers.
+///
+/// The network is run by a deterministic round-robin scheduler. Each node,
+/// in the order they were added, runs until it halts or waits on input,
+/// and then everything it wrote is delivered to the nodes it is connected
+/// to. This repeats until every node has halted.
+pub struct Network {
+    nodes: Vec<Computer<QueueIo>>,
+    edges: Vec<(NodeId, NodeId)>,
+    sinks: Vec<NodeId>,
+}
+
+impl Network {
+    pub fn new() -> Self {
+        Self {
+            nodes: Vec::new(),
+            edges: Vec::new(),
+            sinks: Vec::new(),
+        }
+    }
+
+    /// Adds a computer running the program to the network.
+    pub fn add_node(&mut self, program: Vec<i64>) -> NodeId {
+        self.nodes.push(Computer::load_program(program, QueueIo::new()));
+        self.nodes.len() - 1
+    }
+
+    /// Declares that everything `from` writes is to be read by `to`.
+    /// A node may be connected to several others, and to itself.
+    pub fn connect(&mut self, from: NodeId, to: NodeId) {
+        assert!(from < self.nodes.len() && to < self.nodes.len(), "Cannot connect unknown nodes");
+        self.edges.push((from, to));
+    }
+
+    /// Declares that the outputs of `node` are to be returned from `run`.
+    pub fn add_sink(&mut self, node: NodeId) {
+        assert!(node < self.nodes.len(), "Cannot make an unknown node a sink");
+        self.sinks.push(node);
+    }
+
+    /// Queues an initial input, such as a phase setting, for the node.
+    pub fn push_input(&mut self, node: NodeId, value: i64) {
+        self.nodes[node].io_system.push_input(value);
+    }
+
+    /// Returns the computer for the node, for example to inspect its state
+    /// once the network has run.
+    pub fn node(&self, node: NodeId) -> &Computer<QueueIo> {
+        &self.nodes[node]
+    }
+
+    /// Runs the network until every node has halted. Returns everything
+    /// written by each sink, in the order the sinks were added.
+    pub fn run(&mut self) -> Result<Vec<Vec<i64>>, NetworkError> {
+        let mut sink_outputs = vec![Vec::new(); self.sinks.len()];
+
+        loop {
+            let mut produced_output = false;
+
+            for node in 0..self.nodes.len() {
+                if let ExecutionState::Halted(_) = self.nodes[node].execution_state {
+                    continue;
+                }
+
+                let state = self.nodes[node].run();
+                if let ExecutionState::Faulted { .. } = state {
+                    return Err(NetworkError::Faulted { node, state });
+                }
+
+                let outputs = self.nodes[node].io_system.drain_output().collect::<Vec<_>>();
+                if outputs.is_empty() {
+                    continue;
+                }
+                produced_output = true;
+
+                for &(_, to) in self.edges.iter().filter(|&&(from, _)| from == node) {
+                    self.nodes[to].io_system.extend_input(outputs.iter().copied());
+                }
+
+                for (sink, sink_output) in self.sinks.iter().zip(&mut sink_outputs) {
+                    if *sink == node {
+                        sink_output.extend(&outputs);
+                    }
+                }
+            }
+
+            let all_halted = self.nodes.iter()
+                .all(|n| matches!(n.execution_state, ExecutionState::Halted(_)));
+            if all_halted {
+                return Ok(sink_outputs);
+            }
+
+            // Only new output can unblock a node that is waiting on input.
+            if !produced_output {
+                return Err(NetworkError::Deadlock);
+            }
+        }
+    }
+}
+
+impl Default for Network {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day7_feedback_program() -> Vec<i64> {
+        vec![
+            3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,
+            27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5
+        ]
+    }
+
+    #[test]
+    pub fn pipeline_passes_outputs_along() {
+        let mut network = Network::new();
+        // Doubles its input, then adds one to its input.
+        let double = network.add_node(vec![3, 7, 1002, 7, 2, 7, 104, 0, 99]);
+        let add_one = network.add_node(vec![3, 7, 1001, 7, 1, 7, 104, 0, 99]);
+        network.connect(double, add_one);
+        network.add_sink(add_one);
+        network.push_input(double, 20);
+
+        assert_eq!(network.run(), Ok(vec![vec![41]]));
+    }
+
+    #[test]
+    pub fn feedback_loop_runs_until_all_nodes_halt() {
+        let mut network = Network::new();
+        let amps = [9, 8, 7, 6, 5].iter()
+            .map(|&phase_setting| {
+                let amp = network.add_node(day7_feedback_program());
+                network.push_input(amp, phase_setting);
+                amp
+            })
+            .collect::<Vec<_>>();
+
+        for i in 0..amps.len() {
+            network.connect(amps[i], amps[(i + 1) % amps.len()]);
+        }
+        network.add_sink(amps[4]);
+        network.push_input(amps[0], 0);
+
+        let outputs = network.run().unwrap();
+        assert_eq!(outputs[0].last(), Some(&139629729));
+    }
+
+    #[test]
+    pub fn starved_network_reports_deadlock() {
+        let mut network = Network::new();
+        let a = network.add_node(vec![3, 0, 99]);
+        let b = network.add_node(vec![3, 0, 4, 0, 99]);
+        network.connect(b, a);
+        assert_eq!(network.run(), Err(NetworkError::Deadlock));
+    }
+
+    #[test]
+    pub fn faulting_node_stops_the_network() {
+        let mut network = Network::new();
+        network.add_node(vec![104, 1, 99]);
+        let bad = network.add_node(vec![98]);
+        match network.run() {
+            Err(NetworkError::Faulted { node, .. }) => assert_eq!(node, bad),
+            result => panic!("Expected a fault, got {:?}", result),
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use crate::Word;
+
+/// The reasons that the text of a program can be rejected.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ParseErrorKind {
+    /// There is nothing between two commas (or before the first or after
+    /// the last one).
+    MissingWord,
+    /// The text between two commas is not a number.
+    BadWord(String),
+}
+
+/// An error from `Program::parse`, along with the byte offset in the text
+/// of the word that could not be parsed.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ParseError {
+    pub offset: usize,
+    pub kind: ParseErrorKind,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Byte {}: ", self.offset)?;
+        match &self.kind {
+            ParseErrorKind::MissingWord => write!(f, "expected a number"),
+            ParseErrorKind::BadWord(word) => write!(f, "cannot parse '{}' as a number", word),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+/// An Intcode program: the words that are loaded into a computer's memory.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Program<W = i64> {
+    words: Vec<W>,
+}
+
+impl<W: Word> Program<W> {
+    /// Parses a program from its usual textual form, a comma separated list
+    /// of numbers. Whitespace (including newlines) around the numbers is
+    /// ignored. Text that is entirely whitespace is an empty program.
+    pub fn parse(source: &str) -> Result<Self, ParseError> {
+        if source.trim().is_empty() {
+            return Ok(Self::default());
+        }
+
+        let mut words = Vec::new();
+        let mut offset = 0;
+
+        for token in source.split(',') {
+            let trimmed = token.trim_start();
+            let word_offset = offset + token.len() - trimmed.len();
+            let trimmed = trimmed.trim_end();
+
+            if trimmed.is_empty() {
+                return Err(ParseError { offset: word_offset, kind: ParseErrorKind::MissingWord });
+            }
+
+            match trimmed.parse::<W>() {
+                Ok(word) => words.push(word),
+                Err(_) => {
+                    let kind = ParseErrorKind::BadWord(trimmed.to_string());
+                    return Err(ParseError { offset: word_offset, kind });
+                }
+            }
+
+            // Skip the token and the comma after it.
+            offset += token.len() + 1;
+        }
+
+        Ok(Self { words })
+    }
+
+    /// Reads and parses a program from a file. If the text cannot be
+    /// parsed the error has kind `InvalidData` and wraps a `ParseError`.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::parse_io(&fs::read_to_string(path)?)
+    }
+
+    /// Reads everything from `reader` (such as stdin) and parses it as a
+    /// program, in the same way as `from_file`.
+    pub fn from_reader<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut source = String::new();
+        reader.read_to_string(&mut source)?;
+        Self::parse_io(&source)
+    }
+
+    fn parse_io(source: &str) -> io::Result<Self> {
+        Self::parse(source).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    pub fn words(&self) -> &[W] {
+        &self.words
+    }
+
+    pub fn into_words(self) -> Vec<W> {
+        self.words
+    }
+
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+}
+
+impl<W> From<Vec<W>> for Program<W> {
+    fn from(words: Vec<W>) -> Self {
+        Self { words }
+    }
+}
+
+impl<W> From<Program<W>> for Vec<W> {
+    fn from(program: Program<W>) -> Self {
+        program.words
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary::through_temp_file;
+
+    fn parse(source: &str) -> Result<Vec<i64>, ParseError> {
+        Program::parse(source).map(Program::into_words)
+    }
+
+    #[test]
+    pub fn parses_comma_separated_words() {
+        assert_eq!(parse("1,0,-3,99"), Ok(vec![1, 0, -3, 99]));
+        assert_eq!(parse("104,1125899906842624,99"), Ok(vec![104, 1125899906842624, 99]));
+    }
+
+    #[test]
+    pub fn ignores_whitespace_and_newlines() {
+        assert_eq!(parse(" 1, 2,\n3 ,\r\n4\n"), Ok(vec![1, 2, 3, 4]));
+        assert_eq!(parse("  \n"), Ok(vec![]));
+    }
+
+    #[test]
+    pub fn reports_byte_offsets_of_bad_words() {
+        assert_eq!(parse("1,2,x3,4"), Err(ParseError { offset: 4, kind: ParseErrorKind::BadWord("x3".into()) }));
+        assert_eq!(parse("1,\n  2 3"), Err(ParseError { offset: 5, kind: ParseErrorKind::BadWord("2 3".into()) }));
+        assert_eq!(parse("1,,2"), Err(ParseError { offset: 2, kind: ParseErrorKind::MissingWord }));
+        assert_eq!(parse("1,2,\n"), Err(ParseError { offset: 5, kind: ParseErrorKind::MissingWord }));
+        assert_eq!(parse("1,99999999999999999999").unwrap_err().offset, 2);
+        assert_eq!(parse("1,,2").unwrap_err().to_string(), "Byte 2: expected a number");
+    }
+
+    #[test]
+    pub fn word_type_limits_what_parses() {
+        assert!(Program::<i32>::parse("104,1125899906842624,99").is_err());
+        assert_eq!(Program::<i128>::parse("1,99999999999999999999").unwrap().words(), &[1, 99999999999999999999]);
+    }
+
+    #[test]
+    pub fn reads_from_readers_and_files() {
+        let program = Program::<i64>::from_reader("3,0,4,0,99\n".as_bytes()).unwrap();
+        assert_eq!(program.words(), &[3, 0, 4, 0, 99]);
+
+        let err = Program::<i64>::from_reader("3,x".as_bytes()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "Byte 2: cannot parse 'x' as a number");
+
+        let program = through_temp_file("program", |path| fs::write(path, "1,2,\n3"), |path| Program::<i64>::from_file(path)).unwrap();
+        assert_eq!(program.into_words(), vec![1, 2, 3]);
+
+        assert!(Program::<i64>::from_file("/no/such/program.txt").is_err());
+    }
+}
@@ -0,0 +1,307 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use crate::{ExecutionState, Fault, Memory};
+use crate::binary::{invalid_data, read_u8, read_u64, read_i64, write_u64, write_i64};
+
+/// Identifies a file as a saved `MachineState`.
+const MAGIC: &[u8; 4] = b"ICSS";
+const VERSION: u8 = 2;
+
+/// The complete state of a computer (apart from its IO system), as
+/// returned by `Computer::snapshot`. A snapshot can be restored into
+/// any number of computers, for example to run many variations of a
+/// program after an expensive setup phase.
+///
+/// Only snapshots of computers with `i64` words can be saved to files.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MachineState<W = i64> {
+    pub memory: Memory<W>,
+    pub instruction_pointer: usize,
+    pub relative_base: i64,
+    pub execution_state: ExecutionState<W>,
+    /// Input given to `Computer::provide_input` that has not yet been read.
+    pub pending_input: Vec<W>,
+}
+
+impl MachineState {
+    /// Writes the state in a compact binary format. All numbers are
+    /// little-endian.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        write_u64(writer, self.instruction_pointer as u64)?;
+        write_i64(writer, self.relative_base)?;
+        write_execution_state(writer, self.execution_state)?;
+        write_memory(writer, &self.memory)?;
+        write_words(writer, &self.pending_input)
+    }
+
+    /// Reads a state written by `write_to`.
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("Not a machine state snapshot"));
+        }
+
+        let version = read_u8(reader)?;
+        if version != VERSION {
+            return Err(invalid_data(&format!("Unsupported snapshot version {}", version)));
+        }
+
+        let instruction_pointer = read_u64(reader)? as usize;
+        let relative_base = read_i64(reader)?;
+        let execution_state = read_execution_state(reader)?;
+        let memory = read_memory(reader)?;
+        let pending_input = read_words(reader)?;
+
+        Ok(Self { memory, instruction_pointer, relative_base, execution_state, pending_input })
+    }
+
+    /// Saves the state to a file, replacing it if it exists.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_to(&mut writer)?;
+        writer.flush()
+    }
+
+    /// Loads a state saved by `save`.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::read_from(&mut BufReader::new(File::open(path)?))
+    }
+}
+
+fn write_words<W: Write>(writer: &mut W, words: &[i64]) -> io::Result<()> {
+    write_u64(writer, words.len() as u64)?;
+    for &word in words {
+        write_i64(writer, word)?;
+    }
+    Ok(())
+}
+
+/// Writes the dense part of memory as a list of words, followed by the
+/// non-zero words of the sparse part as address and value pairs.
+fn write_memory<W: Write>(writer: &mut W, memory: &Memory) -> io::Result<()> {
+    write_u64(writer, memory.dense_limit() as u64)?;
+    write_u64(writer, memory.max_address() as u64)?;
+    write_words(writer, memory.dense())?;
+
+    let sparse = memory.sparse_words().collect::<Vec<_>>();
+    write_u64(writer, sparse.len() as u64)?;
+    for (address, value) in sparse {
+        write_u64(writer, address as u64)?;
+        write_i64(writer, value)?;
+    }
+    Ok(())
+}
+
+fn read_words<R: Read>(reader: &mut R) -> io::Result<Vec<i64>> {
+    let len = read_u64(reader)?;
+    // Don't trust the length for the allocation, a corrupt file could
+    // claim to contain anything.
+    let mut words = Vec::with_capacity(len.min(1 << 20) as usize);
+    for _ in 0..len {
+        words.push(read_i64(reader)?);
+    }
+    Ok(words)
+}
+
+fn read_memory<R: Read>(reader: &mut R) -> io::Result<Memory> {
+    let dense_limit = read_u64(reader)? as usize;
+    let max_address = read_u64(reader)? as usize;
+    let mut memory = Memory::with_dense_limit(read_words(reader)?, dense_limit);
+    memory.set_max_address(max_address);
+
+    let sparse_count = read_u64(reader)?;
+    for _ in 0..sparse_count {
+        let address = read_u64(reader)? as usize;
+        let value = read_i64(reader)?;
+        if address < memory.dense_limit() || address > max_address {
+            return Err(invalid_data(&format!("Sparse address {} is out of range", address)));
+        }
+        memory.set(address, value);
+    }
+    Ok(memory)
+}
+
+fn write_execution_state<W: Write>(writer: &mut W, state: ExecutionState) -> io::Result<()> {
+    match state {
+        ExecutionState::Running => writer.write_all(&[0]),
+        ExecutionState::Halted(value) => {
+            writer.write_all(&[1])?;
+            write_i64(writer, value)
+        },
+        ExecutionState::WaitingOnInput => writer.write_all(&[2]),
+        ExecutionState::Faulted { ip, word, fault } => {
+            writer.write_all(&[3])?;
+            write_u64(writer, ip as u64)?;
+            write_i64(writer, word)?;
+            let (tag, detail) = match fault {
+                Fault::BadOpcode => (0, 0),
+                Fault::BadMode => (1, 0),
+                Fault::NegativeAddress(address) => (2, address),
+                Fault::ImmediateWrite => (3, 0),
+                Fault::IpOutOfRange(address) => (4, address),
+                Fault::ArithmeticOverflow => (5, 0),
+                Fault::AddressLimitExceeded(address) => (6, address as i64),
+            };
+            writer.write_all(&[tag])?;
+            write_i64(writer, detail)
+        },
+        ExecutionState::BudgetExhausted => writer.write_all(&[4]),
+    }
+}
+
+fn read_execution_state<R: Read>(reader: &mut R) -> io::Result<ExecutionState> {
+    let state = match read_u8(reader)? {
+        0 => ExecutionState::Running,
+        1 => ExecutionState::Halted(read_i64(reader)?),
+        2 => ExecutionState::WaitingOnInput,
+        3 => {
+            let ip = read_u64(reader)? as usize;
+            let word = read_i64(reader)?;
+            let tag = read_u8(reader)?;
+            let detail = read_i64(reader)?;
+            let fault = match tag {
+                0 => Fault::BadOpcode,
+                1 => Fault::BadMode,
+                2 => Fault::NegativeAddress(detail),
+                3 => Fault::ImmediateWrite,
+                4 => Fault::IpOutOfRange(detail),
+                5 => Fault::ArithmeticOverflow,
+                6 => Fault::AddressLimitExceeded(detail as usize),
+                n => return Err(invalid_data(&format!("Unknown fault {}", n))),
+            };
+            ExecutionState::Faulted { ip, word, fault }
+        },
+        4 => ExecutionState::BudgetExhausted,
+        n => return Err(invalid_data(&format!("Unknown execution state {}", n))),
+    };
+
+    Ok(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble, Computer, QueueIo};
+    use crate::binary::through_temp_file;
+
+    /// Adds up everything it reads, outputting the running total each time.
+    fn summing_program() -> Vec<i64> {
+        assemble("
+            loop:   in [value]
+                    add [total], [value], [total]
+                    out [total]
+                    jt #1, #loop
+            value:  db 0
+            total:  db 100
+        ").unwrap()
+    }
+
+    #[test]
+    pub fn restored_computers_continue_from_the_snapshot() {
+        let mut computer = Computer::load_program(summing_program(), QueueIo::new());
+        computer.provide_input(5);
+        computer.run_until_output();
+        let snapshot = computer.snapshot();
+
+        for &(input, expected) in &[(1, 106), (10, 115)] {
+            let mut fork = Computer::from_snapshot(&snapshot, QueueIo::new());
+            fork.provide_input(input);
+            assert_eq!(fork.run_until_output(), Some(expected));
+        }
+
+        computer.provide_input(2);
+        computer.run_until_output();
+        computer.restore(&snapshot);
+        computer.provide_input(3);
+        assert_eq!(computer.run_until_output(), Some(108));
+    }
+
+    #[test]
+    pub fn restoring_forgets_coverage_of_the_abandoned_run() {
+        let mut computer = Computer::load_program(summing_program(), QueueIo::new());
+        computer.enable_coverage();
+        computer.provide_input(5);
+        computer.run_until_output();
+        let snapshot = computer.snapshot();
+
+        computer.provide_input(2);
+        computer.run_until_output();
+        assert_eq!(computer.coverage().unwrap().hits(0).executed, 2);
+
+        computer.restore(&snapshot);
+        assert_eq!(computer.coverage().unwrap().iter().count(), 0);
+        computer.provide_input(3);
+        computer.run_until_output();
+        let coverage = computer.coverage().unwrap();
+        assert_eq!(coverage.hits(0).executed, 1);
+        assert_eq!(coverage.executed_addresses().into_iter().collect::<Vec<_>>(), vec![0, 2, 6, 8]);
+    }
+
+    #[test]
+    pub fn snapshot_includes_pending_input() {
+        let mut computer = Computer::load_program(summing_program(), QueueIo::new());
+        computer.provide_input(1);
+        computer.provide_input(2);
+        computer.run_until_output();
+        let snapshot = computer.snapshot();
+        assert_eq!(snapshot.pending_input, vec![2]);
+
+        let mut fork = Computer::from_snapshot(&snapshot, QueueIo::new());
+        assert_eq!(fork.run_until_output(), Some(103));
+    }
+
+    #[test]
+    pub fn binary_format_round_trips() {
+        let states = vec![
+            ExecutionState::Running,
+            ExecutionState::Halted(-5),
+            ExecutionState::WaitingOnInput,
+            ExecutionState::Faulted { ip: 3, word: 98, fault: Fault::BadOpcode },
+            ExecutionState::Faulted { ip: 7, word: 1, fault: Fault::NegativeAddress(-9) },
+            ExecutionState::Faulted { ip: 0, word: 1105, fault: Fault::IpOutOfRange(1000) },
+            ExecutionState::Faulted { ip: 4, word: 1101, fault: Fault::AddressLimitExceeded(1000) },
+            ExecutionState::BudgetExhausted,
+        ];
+
+        for execution_state in states {
+            let mut memory = Memory::new(vec![1, -2, i64::MAX, i64::MIN]);
+            memory.set(1 << 40, 7);
+            memory.set_max_address(1 << 41);
+            let state = MachineState {
+                memory,
+                instruction_pointer: 2,
+                relative_base: -40,
+                execution_state,
+                pending_input: vec![8, 9],
+            };
+
+            let mut bytes = Vec::new();
+            state.write_to(&mut bytes).unwrap();
+            assert_eq!(MachineState::read_from(&mut bytes.as_slice()).unwrap(), state);
+        }
+    }
+
+    #[test]
+    pub fn read_rejects_bad_data() {
+        let err = MachineState::read_from(&mut &b"NOPE"[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let err = MachineState::read_from(&mut &b"ICSS\x02"[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    pub fn save_and_load_a_file() {
+        let mut computer = Computer::load_program(summing_program(), QueueIo::new());
+        computer.provide_input(5);
+        computer.run();
+        let snapshot = computer.snapshot();
+
+        let loaded = through_temp_file("snapshot", |path| snapshot.save(path), |path| MachineState::load(path)).unwrap();
+        assert_eq!(loaded, snapshot);
+    }
+}
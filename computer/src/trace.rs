@@ -0,0 +1,351 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use crate::disassembler::format_operand;
+use crate::{Fault, Instruction, Word};
+
+/// A write to memory made by an instruction.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MemoryWrite<W = i64> {
+    pub address: usize,
+    pub old: W,
+    pub new: W,
+}
+
+/// IO performed by an instruction.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum IoEvent<W = i64> {
+    Read(W),
+    Write(W),
+}
+
+/// Everything that happened when a single instruction was executed.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TraceEvent<W = i64> {
+    /// The address of the instruction.
+    pub ip: usize,
+    pub instruction: Instruction,
+    /// The raw parameters of the instruction, as stored in memory.
+    pub parameters: Vec<W>,
+    /// The values of the operands that were read, in the order they were
+    /// read, after resolving their parameter modes.
+    pub operands: Vec<W>,
+    pub memory_writes: Vec<MemoryWrite<W>>,
+    /// The old and new values of the Relative Base Offset register, if
+    /// the instruction changed it.
+    pub relative_base_change: Option<(i64, i64)>,
+    pub io: Option<IoEvent<W>>,
+}
+
+impl<W: Word> TraceEvent<W> {
+    pub(crate) fn new(ip: usize, instruction: Instruction, parameters: Vec<W>) -> Self {
+        Self {
+            ip,
+            instruction,
+            parameters,
+            operands: Vec::new(),
+            memory_writes: Vec::new(),
+            relative_base_change: None,
+            io: None,
+        }
+    }
+
+    /// Renders the event as a single line of JSON.
+    pub fn to_json(&self) -> String {
+        let modes = self.instruction.parameter_modes().iter()
+            .map(|mode| format!("\"{:?}\"", mode))
+            .collect::<Vec<_>>();
+        let writes = self.memory_writes.iter()
+            .map(|w| format!("{{\"address\":{},\"old\":{},\"new\":{}}}", w.address, w.old, w.new))
+            .collect::<Vec<_>>();
+        let relative_base = match self.relative_base_change {
+            Some((old, new)) => format!("{{\"old\":{},\"new\":{}}}", old, new),
+            None => "null".to_string(),
+        };
+        let io = match &self.io {
+            Some(IoEvent::Read(value)) => format!("{{\"read\":{}}}", value),
+            Some(IoEvent::Write(value)) => format!("{{\"write\":{}}}", value),
+            None => "null".to_string(),
+        };
+
+        format!(
+            "{{\"ip\":{},\"mnemonic\":\"{}\",\"modes\":[{}],\"parameters\":{},\"operands\":{},\
+            \"writes\":[{}],\"relative_base\":{},\"io\":{}}}",
+            self.ip, self.instruction.mnemonic(), modes.join(","), format_words(&self.parameters),
+            format_words(&self.operands),
+            writes.join(","), relative_base, io)
+    }
+}
+
+/// Renders a list of words as `[1, 2, 3]`.
+fn format_words<W: Word>(words: &[W]) -> String {
+    let words = words.iter().map(|word| word.to_string()).collect::<Vec<_>>();
+    format!("[{}]", words.join(", "))
+}
+
+impl<W: Word> fmt::Display for TraceEvent<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:>6}: {}", self.ip, self.instruction.mnemonic())?;
+        let modes = self.instruction.parameter_modes();
+        for (i, (mode, value)) in modes.iter().zip(&self.parameters).enumerate() {
+            let separator = if i == 0 { " " } else { ", " };
+            write!(f, "{}{}", separator, format_operand(*mode, value))?;
+        }
+
+        let mut notes = Vec::new();
+        if !self.operands.is_empty() {
+            notes.push(format!("operands {}", format_words(&self.operands)));
+        }
+        for w in &self.memory_writes {
+            notes.push(format!("[{}] {} -> {}", w.address, w.old, w.new));
+        }
+        if let Some((old, new)) = self.relative_base_change {
+            notes.push(format!("rb {} -> {}", old, new));
+        }
+        match &self.io {
+            Some(IoEvent::Read(value)) => notes.push(format!("read {}", value)),
+            Some(IoEvent::Write(value)) => notes.push(format!("wrote {}", value)),
+            None => {},
+        }
+
+        if !notes.is_empty() {
+            write!(f, "    ; {}", notes.join("; "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Receives an event for every instruction that a computer executes.
+/// Attach one with `Computer::set_tracer`.
+///
+/// To get at a tracer once it has been attached, wrap it in an
+/// `Arc<Mutex<_>>` and attach a clone of that.
+pub trait Tracer<W = i64> {
+    /// Called after each instruction has executed. A Read that has to wait
+    /// for input has not executed, so is not traced until it is retried.
+    fn trace(&mut self, event: &TraceEvent<W>);
+
+    /// Called when the computer faults while executing the instruction
+    /// at `ip`, whose raw value is `word`.
+    fn fault(&mut self, _ip: usize, _word: W, _fault: Fault) {}
+}
+
+impl<T, W> Tracer<W> for Arc<Mutex<T>>
+    where T: Tracer<W>
+{
+    fn trace(&mut self, event: &TraceEvent<W>) {
+        self.lock().expect("Tracer lock poisoned").trace(event);
+    }
+
+    fn fault(&mut self, ip: usize, word: W, fault: Fault) {
+        self.lock().expect("Tracer lock poisoned").fault(ip, word, fault);
+    }
+}
+
+/// A tracer that writes each event as a line of JSON. If writing fails,
+/// tracing stops and the error is kept so it can be inspected later.
+pub struct JsonLinesTracer<W: Write> {
+    writer: W,
+    error: Option<io::Error>,
+}
+
+impl JsonLinesTracer<BufWriter<File>> {
+    /// Creates (or truncates) the file and traces to it.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write> JsonLinesTracer<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, error: None }
+    }
+
+    /// Returns the error that stopped tracing, if there was one.
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    /// Flushes the writer and returns it.
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_line(&mut self, line: &str) {
+        if self.error.is_none() {
+            if let Err(e) = writeln!(self.writer, "{}", line) {
+                self.error = Some(e);
+            }
+        }
+    }
+}
+
+impl<T: Write, W: Word> Tracer<W> for JsonLinesTracer<T> {
+    fn trace(&mut self, event: &TraceEvent<W>) {
+        self.write_line(&event.to_json());
+    }
+
+    fn fault(&mut self, ip: usize, word: W, fault: Fault) {
+        let line = format!("{{\"ip\":{},\"word\":{},\"fault\":\"{:?}\"}}", ip, word, fault);
+        self.write_line(&line);
+        if let Err(e) = self.writer.flush() {
+            self.error.get_or_insert(e);
+        }
+    }
+}
+
+/// A tracer that keeps the last few events, so that when a fault occurs
+/// the history leading up to it can be printed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RingBufferTracer<W = i64> {
+    capacity: usize,
+    events: VecDeque<TraceEvent<W>>,
+    fault: Option<(usize, W, Fault)>,
+}
+
+impl<W: Word> RingBufferTracer<W> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            events: VecDeque::with_capacity(capacity),
+            fault: None,
+        }
+    }
+
+    /// Returns the retained events, oldest first.
+    pub fn events(&self) -> impl Iterator<Item = &TraceEvent<W>> {
+        self.events.iter()
+    }
+
+    /// Returns the fault that stopped the computer, if there was one.
+    pub fn fault(&self) -> Option<(usize, W, Fault)> {
+        self.fault.clone()
+    }
+
+    /// Renders the retained events one per line, followed by the fault.
+    pub fn history(&self) -> String {
+        let mut history = String::new();
+        for event in &self.events {
+            history.push_str(&event.to_string());
+            history.push('\n');
+        }
+        if let Some((ip, word, fault)) = &self.fault {
+            history.push_str(&format!("{:>6}: FAULT {:?} (word {})\n", ip, fault, word));
+        }
+        history
+    }
+}
+
+impl<W: Word> Tracer<W> for RingBufferTracer<W> {
+    fn trace(&mut self, event: &TraceEvent<W>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+        }
+        self.events.push_back(event.clone());
+    }
+
+    fn fault(&mut self, ip: usize, word: W, fault: Fault) {
+        self.fault = Some((ip, word, fault));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble, Computer, ExecutionState, ParameterMode::*, QueueIo};
+
+    fn traced_computer(source: &str, capacity: usize) -> (Computer<QueueIo>, Arc<Mutex<RingBufferTracer>>) {
+        let mut computer = Computer::load_program(assemble(source).unwrap(), QueueIo::new());
+        let tracer = Arc::new(Mutex::new(RingBufferTracer::new(capacity)));
+        computer.set_tracer(Box::new(tracer.clone()));
+        (computer, tracer)
+    }
+
+    #[test]
+    pub fn events_record_operands_writes_and_io() {
+        let (mut computer, tracer) = traced_computer("
+                in [x]
+                arb #5
+                mul [x], #3, rb+7
+                out [y]
+                hlt
+            x:  db 4
+            y:  db 10
+        ", 10);
+        computer.provide_input(7);
+        computer.run();
+
+        let tracer = tracer.lock().unwrap();
+        let events = tracer.events().collect::<Vec<_>>();
+        assert_eq!(events.len(), 5);
+
+        assert_eq!(events[0].ip, 0);
+        assert_eq!(events[0].instruction, Instruction::Read(Position));
+        assert_eq!(events[0].io, Some(IoEvent::Read(7)));
+        assert_eq!(events[0].memory_writes, vec![MemoryWrite { address: 11, old: 4, new: 7 }]);
+
+        assert_eq!(events[1].relative_base_change, Some((0, 5)));
+
+        assert_eq!(events[2].parameters, vec![11, 3, 7]);
+        assert_eq!(events[2].operands, vec![7, 3]);
+        assert_eq!(events[2].memory_writes, vec![MemoryWrite { address: 12, old: 10, new: 21 }]);
+
+        assert_eq!(events[3].io, Some(IoEvent::Write(21)));
+        assert_eq!(events[4].instruction, Instruction::Halt);
+    }
+
+    #[test]
+    pub fn ring_buffer_keeps_history_up_to_a_fault() {
+        let (mut computer, tracer) = traced_computer("
+            out #1
+            out #2
+            out #3
+            db 98
+        ", 2);
+        assert!(matches!(computer.run(), ExecutionState::Faulted { .. }));
+
+        let tracer = tracer.lock().unwrap();
+        assert_eq!(tracer.fault(), Some((6, 98, Fault::BadOpcode)));
+        assert_eq!(tracer.history().lines().map(str::trim).collect::<Vec<_>>(), vec![
+            "2: OUT #2    ; operands [2]; wrote 2",
+            "4: OUT #3    ; operands [3]; wrote 3",
+            "6: FAULT BadOpcode (word 98)",
+        ]);
+    }
+
+    #[test]
+    pub fn waiting_read_is_traced_when_retried() {
+        let (mut computer, tracer) = traced_computer("in [3]\nhlt", 10);
+        assert_eq!(computer.run(), ExecutionState::WaitingOnInput);
+        assert_eq!(tracer.lock().unwrap().events().count(), 0);
+
+        computer.provide_input(1);
+        computer.run();
+        assert_eq!(tracer.lock().unwrap().events().count(), 2);
+    }
+
+    #[test]
+    pub fn json_lines_tracer_writes_one_line_per_event() {
+        let mut computer = Computer::load_program(assemble("add #1, #2, [0]\nout [0]\nhlt").unwrap(), QueueIo::new());
+        let tracer = Arc::new(Mutex::new(JsonLinesTracer::new(Vec::new())));
+        computer.set_tracer(Box::new(tracer.clone()));
+        computer.run();
+        drop(computer);
+
+        let tracer = Arc::try_unwrap(tracer).ok().unwrap().into_inner().unwrap();
+        let output = String::from_utf8(tracer.into_inner().unwrap()).unwrap();
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "{\"ip\":0,\"mnemonic\":\"ADD\",\"modes\":[\"Immediate\",\"Immediate\",\"Position\"],\
+            \"parameters\":[1, 2, 0],\"operands\":[1, 2],\"writes\":[{\"address\":0,\"old\":1101,\"new\":3}],\
+            \"relative_base\":null,\"io\":null}");
+        assert!(lines[1].ends_with("\"io\":{\"write\":3}}"));
+    }
+}
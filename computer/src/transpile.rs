@@ -0,0 +1,433 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use crate::disassembler::{decode_at, find_reachable_instructions, static_jump_target};
+use crate::{Image, Instruction, Line, ParameterMode};
+
+/// The indentation of statements inside a match arm of the generated code.
+const INDENT: &str = "                ";
+
+/// Translates a program into the Rust source of a function that runs it
+/// natively. The function is called `function_name`, which must be a valid
+/// Rust identifier, and has the signature
+///
+/// ```text
+/// pub fn function_name<I: computer::ComputerIo>(io: I) -> computer::Computer<I>
+/// ```
+///
+/// It runs the program from its entry point, doing IO through `io`, and
+/// returns a computer in the state that the program stopped in: halted,
+/// faulted or waiting on input. A computer that is waiting on input can be
+/// given more and run again, as usual.
+///
+/// The code that can be found by following the flow of control from the
+/// entry point is translated into a loop over a `match` on the instruction
+/// pointer, with an arm for each basic block. It is meant for programs that
+/// do not modify their own code. If the program does, or jumps to code that
+/// was not found, or is about to fault, the function hands the program over
+/// to the interpreter to carry on, so the results are always the same as if
+/// the program had been interpreted from the start.
+pub fn transpile(image: &Image, function_name: &str) -> String {
+    let translation = Translation::new(image);
+    let mut out = String::new();
+    translation.write_function(&mut out, function_name).expect("Writing to a String cannot fail");
+    out
+}
+
+/// The code found in a program, ready to be written out.
+struct Translation<'a> {
+    image: &'a Image,
+    /// The instructions that will be translated, by address.
+    instructions: BTreeMap<usize, (Instruction, Vec<i64>)>,
+    /// The addresses that start a basic block, i.e. that can be jumped to.
+    leaders: BTreeSet<usize>,
+}
+
+impl<'a> Translation<'a> {
+    fn new(image: &'a Image) -> Self {
+        let program = &image.program;
+        let entry = image.entry_point.unwrap_or(0);
+
+        // As well as the entry point, start from any return addresses: an
+        // address just after an instruction that the program uses as an
+        // Immediate mode operand, as in `add #ret, #0, rb+0`. Without these,
+        // code after a subroutine call would not be found.
+        let mut starts = BTreeSet::new();
+        starts.insert(entry);
+        let instructions = loop {
+            let instructions = starts.iter()
+                .flat_map(|&start| find_reachable_instructions(program, start))
+                .filter_map(|address| decode_at(program, address).map(|decoded| (address, decoded)))
+                .collect::<BTreeMap<_, _>>();
+
+            let ends = instructions.iter()
+                .map(|(&address, (_, operands))| address + operands.len() + 1)
+                .collect::<BTreeSet<_>>();
+            let return_addresses = immediate_addresses(&instructions)
+                .filter(|address| ends.contains(address) && !instructions.contains_key(address))
+                .collect::<Vec<_>>();
+
+            if return_addresses.is_empty() {
+                break instructions;
+            }
+            starts.extend(return_addresses);
+        };
+
+        let mut leaders = starts;
+        for (&address, (instruction, operands)) in &instructions {
+            if let Instruction::JumpIfTrue(..) | Instruction::JumpIfFalse(..) = instruction {
+                leaders.extend(static_jump_target(*instruction, operands));
+                leaders.insert(address + operands.len() + 1);
+            }
+        }
+        leaders.extend(immediate_addresses(&instructions));
+        leaders.retain(|address| instructions.contains_key(address));
+
+        Self { image, instructions, leaders }
+    }
+
+    fn write_function(&self, out: &mut String, function_name: &str) -> std::fmt::Result {
+        let program = &self.image.program;
+        let entry = self.image.entry_point.unwrap_or(0);
+
+        writeln!(out, "// Generated from an Intcode program by `computer::transpile`. Do not edit.")?;
+        writeln!(out)?;
+        writeln!(out, "/// Runs the program natively and returns a computer in the state it stopped in.")?;
+        writeln!(out, "#[allow(unused, clippy::all)]")?;
+        writeln!(out, "pub fn {}<I: computer::ComputerIo>(mut io: I) -> computer::Computer<I> {{", function_name)?;
+        writeln!(out, "    use computer::{{Computer, ExecutionState, MachineState, Memory}};")?;
+        writeln!(out)?;
+
+        writeln!(out, "    static PROGRAM: [i64; {}] = [", program.len())?;
+        for chunk in program.chunks(16) {
+            let words = chunk.iter().map(|word| word.to_string()).collect::<Vec<_>>();
+            writeln!(out, "        {},", words.join(", "))?;
+        }
+        writeln!(out, "    ];")?;
+        writeln!(out)?;
+
+        writeln!(out, "    /// Returns true if the address holds part of a translated instruction.")?;
+        writeln!(out, "    fn is_code(address: usize) -> bool {{")?;
+        writeln!(out, "        matches!(address, {})", self.code_ranges().join(" | "))?;
+        writeln!(out, "    }}")?;
+        writeln!(out)?;
+
+        writeln!(out, "    /// Returns the address that a Relative mode parameter refers to, if the")?;
+        writeln!(out, "    /// program is allowed to use it.")?;
+        writeln!(out, "    fn relative(memory: &Memory, rb: i64, offset: i64) -> Option<usize> {{")?;
+        writeln!(out, "        let address = rb.checked_add(offset)?;")?;
+        writeln!(out, "        if address < 0 || address as usize > memory.max_address() {{")?;
+        writeln!(out, "            None")?;
+        writeln!(out, "        }} else {{")?;
+        writeln!(out, "            Some(address as usize)")?;
+        writeln!(out, "        }}")?;
+        writeln!(out, "    }}")?;
+        writeln!(out)?;
+
+        writeln!(out, "    let mut memory = Memory::new(PROGRAM.to_vec());")?;
+        writeln!(out, "    let mut ip: usize = {};", entry)?;
+        writeln!(out, "    let mut rb: i64 = 0;")?;
+        writeln!(out, "    let mut state = ExecutionState::Running;")?;
+        writeln!(out)?;
+
+        let labels = self.image.symbols.iter()
+            .map(|(name, &address)| (address, name))
+            .collect::<BTreeMap<_, _>>();
+
+        writeln!(out, "    'run: loop {{")?;
+        writeln!(out, "        match ip {{")?;
+        for &leader in &self.leaders {
+            match labels.get(&leader) {
+                Some(label) => writeln!(out, "            {} => {{ // {}", leader, label)?,
+                None => writeln!(out, "            {} => {{", leader)?,
+            }
+            self.write_block(out, leader)?;
+            writeln!(out, "            }},")?;
+        }
+        writeln!(out, "            // Not translated, so the interpreter has to take over.")?;
+        writeln!(out, "            _ => break 'run,")?;
+        writeln!(out, "        }}")?;
+        writeln!(out, "    }}")?;
+        writeln!(out)?;
+
+        writeln!(out, "    let snapshot = MachineState {{")?;
+        writeln!(out, "        memory,")?;
+        writeln!(out, "        instruction_pointer: ip,")?;
+        writeln!(out, "        relative_base: rb,")?;
+        writeln!(out, "        execution_state: state,")?;
+        writeln!(out, "        pending_input: Vec::new(),")?;
+        writeln!(out, "    }};")?;
+        writeln!(out, "    let mut computer = Computer::from_snapshot(&snapshot, io);")?;
+        writeln!(out, "    if computer.execution_state == ExecutionState::Running {{")?;
+        writeln!(out, "        computer.run();")?;
+        writeln!(out, "    }}")?;
+        writeln!(out, "    computer")?;
+        writeln!(out, "}}")
+    }
+
+    /// Writes the basic block starting at `leader`, which runs until a jump
+    /// or Halt, or until the next instruction starts another block.
+    fn write_block(&self, out: &mut String, leader: usize) -> std::fmt::Result {
+        let mut address = leader;
+
+        loop {
+            let (instruction, operands) = &self.instructions[&address];
+            let line = Line::Instruction { address, instruction: *instruction, operands: operands.clone() };
+            writeln!(out, "{}// {}", INDENT, line.to_string().trim())?;
+
+            if !self.write_instruction(out, address, *instruction, operands)? {
+                return Ok(());
+            }
+
+            address += operands.len() + 1;
+            if self.leaders.contains(&address) || !self.instructions.contains_key(&address) {
+                return writeln!(out, "{}ip = {};", INDENT, address);
+            }
+        }
+    }
+
+    /// Writes the code for one instruction. Returns true if execution can
+    /// carry on to the next instruction in the block.
+    fn write_instruction(&self, out: &mut String, address: usize, instruction: Instruction, operands: &[i64]) -> Result<bool, std::fmt::Error> {
+        let next = address + operands.len() + 1;
+        // Hands over to the interpreter, which will fault on the instruction.
+        let bail = format!("{{ ip = {}; break 'run; }}", address);
+        let modes = instruction.parameter_modes();
+
+        // An instruction using a negative address always faults.
+        let always_faults = modes.iter().zip(operands)
+            .any(|(&mode, &value)| mode == ParameterMode::Position && value < 0);
+        if always_faults {
+            writeln!(out, "{}{}", INDENT, bail)?;
+            return Ok(false);
+        }
+
+        let read = |name: &str, number: usize| read_operand(name, modes[number], operands[number], &bail);
+        let target = |number: usize| target_address(modes[number], operands[number], &bail);
+
+        match instruction {
+            Instruction::Add(..) | Instruction::Multiply(..) | Instruction::LessThan(..) | Instruction::Equals(..) => {
+                writeln!(out, "{}{}", INDENT, read("x", 0))?;
+                writeln!(out, "{}{}", INDENT, read("y", 1))?;
+                let value = match instruction {
+                    Instruction::Add(..) => format!("match x.checked_add(y) {{ Some(value) => value, None => {} }}", bail),
+                    Instruction::Multiply(..) => format!("match x.checked_mul(y) {{ Some(value) => value, None => {} }}", bail),
+                    Instruction::LessThan(..) => "if x < y { 1 } else { 0 }".to_string(),
+                    _ => "if x == y { 1 } else { 0 }".to_string(),
+                };
+                writeln!(out, "{}let value = {};", INDENT, value)?;
+                writeln!(out, "{}let address = {};", INDENT, target(2))?;
+                writeln!(out, "{}memory.set(address, value);", INDENT)?;
+                self.write_code_check(out, modes[2], operands[2], next)
+            },
+
+            Instruction::Read(..) => {
+                writeln!(out, "{}let address = {};", INDENT, target(0))?;
+                writeln!(out, "{}match io.try_read(\"Enter number: \") {{", INDENT)?;
+                writeln!(out, "{}    Some(value) => memory.set(address, value),", INDENT)?;
+                writeln!(out, "{}    None => {{", INDENT)?;
+                writeln!(out, "{}        ip = {};", INDENT, address)?;
+                writeln!(out, "{}        state = ExecutionState::WaitingOnInput;", INDENT)?;
+                writeln!(out, "{}        break 'run;", INDENT)?;
+                writeln!(out, "{}    }}", INDENT)?;
+                writeln!(out, "{}}}", INDENT)?;
+                self.write_code_check(out, modes[0], operands[0], next)
+            },
+
+            Instruction::Write(..) => {
+                writeln!(out, "{}{}", INDENT, read("x", 0))?;
+                writeln!(out, "{}io.write(x);", INDENT)?;
+                Ok(true)
+            },
+
+            Instruction::JumpIfTrue(..) | Instruction::JumpIfFalse(..) => {
+                let condition = match instruction {
+                    Instruction::JumpIfTrue(..) => "x != 0",
+                    _ => "x == 0",
+                };
+                writeln!(out, "{}{}", INDENT, read("x", 0))?;
+                writeln!(out, "{}if {} {{", INDENT, condition)?;
+                match static_jump_target(instruction, operands) {
+                    Some(target) if self.instructions.contains_key(&target) => {
+                        writeln!(out, "{}    ip = {};", INDENT, target)?;
+                    },
+                    _ => {
+                        writeln!(out, "{}    {}", INDENT, read("target", 1))?;
+                        writeln!(out, "{}    if target < 0 || target as usize > memory.max_address() {}", INDENT, bail)?;
+                        writeln!(out, "{}    ip = target as usize;", INDENT)?;
+                    },
+                }
+                writeln!(out, "{}    continue 'run;", INDENT)?;
+                writeln!(out, "{}}}", INDENT)?;
+                writeln!(out, "{}ip = {};", INDENT, next)?;
+                Ok(false)
+            },
+
+            Instruction::RelativeBaseOffset(..) => {
+                writeln!(out, "{}{}", INDENT, read("x", 0))?;
+                writeln!(out, "{}rb = match rb.checked_add(x) {{ Some(rb) => rb, None => {} }};", INDENT, bail)?;
+                Ok(true)
+            },
+
+            Instruction::Halt => {
+                writeln!(out, "{}ip = {};", INDENT, address)?;
+                writeln!(out, "{}state = ExecutionState::Halted(memory.get(0));", INDENT)?;
+                writeln!(out, "{}break 'run;", INDENT)?;
+                Ok(false)
+            },
+        }
+    }
+
+    /// Writes the check for a write to memory landing on translated code,
+    /// in which case the translation is no longer valid and the interpreter
+    /// has to take over from the next instruction.
+    fn write_code_check(&self, out: &mut String, mode: ParameterMode, operand: i64, next: usize) -> Result<bool, std::fmt::Error> {
+        let handover = format!("ip = {}; break 'run;", next);
+        match mode {
+            ParameterMode::Position if self.is_code(operand as usize) => {
+                writeln!(out, "{}{}", INDENT, handover)?;
+                Ok(false)
+            },
+            ParameterMode::Position => Ok(true),
+            _ => {
+                writeln!(out, "{}if is_code(address) {{ {} }}", INDENT, handover)?;
+                Ok(true)
+            },
+        }
+    }
+
+    fn is_code(&self, address: usize) -> bool {
+        self.instructions.range(..=address).next_back()
+            .is_some_and(|(&start, (_, operands))| address <= start + operands.len())
+    }
+
+    /// Returns the patterns that match the addresses of translated code,
+    /// merging adjacent instructions into one range.
+    fn code_ranges(&self) -> Vec<String> {
+        let mut ranges: Vec<(usize, usize)> = Vec::new();
+        for (&start, (_, operands)) in &self.instructions {
+            let end = start + operands.len();
+            match ranges.last_mut() {
+                Some(last) if start <= last.1 + 1 => last.1 = last.1.max(end),
+                _ => ranges.push((start, end)),
+            }
+        }
+
+        if ranges.is_empty() {
+            return vec!["_ if false".to_string()];
+        }
+        ranges.iter().map(|&(start, end)| format!("{}..={}", start, end)).collect()
+    }
+}
+
+/// Returns the values of every Immediate mode operand of the instructions
+/// that could be addresses.
+fn immediate_addresses<'a>(instructions: &'a BTreeMap<usize, (Instruction, Vec<i64>)>) -> impl Iterator<Item = usize> + 'a {
+    instructions.values().flat_map(|(instruction, operands)| {
+        instruction.parameter_modes().into_iter().zip(operands.clone())
+            .filter(|&(mode, value)| mode == ParameterMode::Immediate && value >= 0)
+            .map(|(_, value)| value as usize)
+    })
+}
+
+/// Returns a statement that reads an operand into the variable `name`.
+fn read_operand(name: &str, mode: ParameterMode, operand: i64, bail: &str) -> String {
+    match mode {
+        ParameterMode::Immediate => format!("let {}: i64 = {};", name, operand),
+        ParameterMode::Position => format!("let {} = memory.get({});", name, operand),
+        ParameterMode::Relative => {
+            format!("let {} = match relative(&memory, rb, {}) {{ Some(address) => memory.get(address), None => {} }};",
+                name, operand, bail)
+        },
+    }
+}
+
+/// Returns an expression for the address that an operand which is written
+/// to refers to.
+fn target_address(mode: ParameterMode, operand: i64, bail: &str) -> String {
+    match mode {
+        ParameterMode::Relative => {
+            format!("match relative(&memory, rb, {}) {{ Some(address) => address, None => {} }}", operand, bail)
+        },
+        _ => format!("{}usize", operand),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble_with_labels;
+
+    fn transpile_source(source: &str) -> String {
+        transpile(&Image::from(assemble_with_labels(source).unwrap()), "program")
+    }
+
+    #[test]
+    pub fn basic_blocks_become_match_arms() {
+        let rust = transpile_source("
+                    in [n]
+            loop:   out [n]
+                    add [n], #-1, [n]
+                    jt [n], #loop
+                    hlt
+            n:      db 0
+        ");
+
+        assert!(rust.contains("pub fn program<I: computer::ComputerIo>(mut io: I) -> computer::Computer<I> {"));
+        assert!(rust.contains("            0 => {\n"));
+        assert!(rust.contains("            2 => { // loop\n"));
+        assert!(rust.contains("            11 => {\n"));
+        assert!(!rust.contains("            4 => {"), "Instructions in the middle of a block are not arms");
+        assert!(rust.contains("matches!(address, 0..=11)"));
+        assert!(rust.contains("// 4: ADD [12], #-1, [12]"));
+        assert!(rust.contains("let value = match x.checked_add(y) { Some(value) => value, None => { ip = 4; break 'run; } };"));
+    }
+
+    #[test]
+    pub fn return_addresses_are_translated() {
+        let rust = transpile_source("
+                    add #ret, #0, rb+0
+                    jt #1, #double
+            ret:    out [value]
+                    hlt
+            double: mul [value], #2, [value]
+                    jt #1, rb+0
+            value:  db 3
+        ");
+
+        assert!(rust.contains("            7 => { // ret\n"));
+        assert!(rust.contains("            10 => { // double\n"));
+        assert!(rust.contains("let target = match relative(&memory, rb, 0)"));
+    }
+
+    #[test]
+    pub fn writes_to_code_hand_over_to_the_interpreter() {
+        let rust = transpile_source("
+            loop:   out #1
+                    add #99, #0, [loop]
+                    jt #1, #loop
+        ");
+        assert!(rust.contains("memory.set(address, value);\n                ip = 6; break 'run;"));
+
+        let rust = transpile_source("
+                    arb #100
+                    in rb+0
+                    hlt
+        ");
+        assert!(rust.contains("if is_code(address) { ip = 4; break 'run; }"));
+    }
+
+    #[test]
+    pub fn instructions_that_always_fault_are_interpreted() {
+        let rust = transpile(&Image::from(vec![1, -3, 0, 0, 99]), "faulty");
+        assert!(rust.contains("            0 => {\n                // 0: ADD [-3], [0], [0]\n                { ip = 0; break 'run; }\n"));
+    }
+
+    #[test]
+    pub fn translation_starts_at_the_entry_point() {
+        let mut image = Image::from(vec![99, 104, 7, 99]);
+        image.entry_point = Some(1);
+        let rust = transpile(&image, "entry");
+        assert!(rust.contains("let mut ip: usize = 1;"));
+        assert!(!rust.contains("            0 => {"));
+    }
+}
@@ -0,0 +1,207 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::hash::Hash;
+use std::str::FromStr;
+
+/// A type that can be stored in the memory of a computer.
+///
+/// Programs see the same behaviour whatever the word type, as long as
+/// their values fit. Small types make memory more compact; big ones let
+/// numerically heavy programs run without overflowing. Addresses, the
+/// instruction pointer and the Relative Base Offset are always handled as
+/// `i64`, so words used as those must fit in one.
+pub trait Word: Clone + fmt::Debug + fmt::Display + Default + Eq + Ord + Hash + FromStr + Send + 'static {
+    fn zero() -> Self;
+    fn one() -> Self;
+
+    /// Converts from an `i64`, if the value fits.
+    fn from_i64(value: i64) -> Option<Self>;
+
+    /// Converts to an `i64`, if the value fits.
+    fn to_i64(&self) -> Option<i64>;
+
+    fn checked_add(&self, other: &Self) -> Option<Self>;
+    fn checked_mul(&self, other: &Self) -> Option<Self>;
+    fn wrapping_add(&self, other: &Self) -> Self;
+    fn wrapping_mul(&self, other: &Self) -> Self;
+    fn saturating_add(&self, other: &Self) -> Self;
+    fn saturating_mul(&self, other: &Self) -> Self;
+
+    fn is_zero(&self) -> bool {
+        *self == Self::zero()
+    }
+
+    /// Converts to an `i64`, clamping values that do not fit to the
+    /// nearest `i64`. Useful when reporting a word that has to be an `i64`,
+    /// such as an address, but is not.
+    fn saturating_to_i64(&self) -> i64 {
+        match self.to_i64() {
+            Some(value) => value,
+            None if *self < Self::zero() => i64::MIN,
+            None => i64::MAX,
+        }
+    }
+}
+
+macro_rules! impl_word_for_primitive {
+    ($($t:ty),*) => {
+        $(
+            impl Word for $t {
+                fn zero() -> Self {
+                    0
+                }
+
+                fn one() -> Self {
+                    1
+                }
+
+                fn from_i64(value: i64) -> Option<Self> {
+                    <$t>::try_from(value).ok()
+                }
+
+                fn to_i64(&self) -> Option<i64> {
+                    i64::try_from(*self).ok()
+                }
+
+                fn checked_add(&self, other: &Self) -> Option<Self> {
+                    <$t>::checked_add(*self, *other)
+                }
+
+                fn checked_mul(&self, other: &Self) -> Option<Self> {
+                    <$t>::checked_mul(*self, *other)
+                }
+
+                fn wrapping_add(&self, other: &Self) -> Self {
+                    <$t>::wrapping_add(*self, *other)
+                }
+
+                fn wrapping_mul(&self, other: &Self) -> Self {
+                    <$t>::wrapping_mul(*self, *other)
+                }
+
+                fn saturating_add(&self, other: &Self) -> Self {
+                    <$t>::saturating_add(*self, *other)
+                }
+
+                fn saturating_mul(&self, other: &Self) -> Self {
+                    <$t>::saturating_mul(*self, *other)
+                }
+            }
+        )*
+    };
+}
+
+impl_word_for_primitive!(i32, i64, i128);
+
+/// Arbitrary precision words, which never overflow. Enable with the
+/// `bigint` feature.
+#[cfg(feature = "bigint")]
+impl Word for num_bigint::BigInt {
+    fn zero() -> Self {
+        Self::from(0)
+    }
+
+    fn one() -> Self {
+        Self::from(1)
+    }
+
+    fn from_i64(value: i64) -> Option<Self> {
+        Some(Self::from(value))
+    }
+
+    fn to_i64(&self) -> Option<i64> {
+        i64::try_from(self).ok()
+    }
+
+    fn checked_add(&self, other: &Self) -> Option<Self> {
+        Some(self + other)
+    }
+
+    fn checked_mul(&self, other: &Self) -> Option<Self> {
+        Some(self * other)
+    }
+
+    fn wrapping_add(&self, other: &Self) -> Self {
+        self + other
+    }
+
+    fn wrapping_mul(&self, other: &Self) -> Self {
+        self * other
+    }
+
+    fn saturating_add(&self, other: &Self) -> Self {
+        self + other
+    }
+
+    fn saturating_mul(&self, other: &Self) -> Self {
+        self * other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Computer, ExecutionState, Fault, QueueIo};
+
+    /// Multiplies 2^40 by itself, which does not fit in an i64.
+    fn big_product_program<W: Word>() -> Vec<W> {
+        [1102, 1 << 40, 1 << 40, 7, 4, 7, 99, 0].iter()
+            .map(|&word| W::from_i64(word).unwrap())
+            .collect()
+    }
+
+    #[test]
+    pub fn conversions() {
+        assert_eq!(i32::from_i64(1 << 40), None);
+        assert_eq!(i32::from_i64(-5), Some(-5));
+        assert_eq!(i128::MAX.to_i64(), None);
+        assert_eq!(i128::MAX.saturating_to_i64(), i64::MAX);
+        assert_eq!(i128::MIN.saturating_to_i64(), i64::MIN);
+        assert!(0i32.is_zero());
+    }
+
+    #[test]
+    pub fn i32_computer_runs_small_programs() {
+        let mut computer = Computer::load_program(vec![1101i32, 100, -1, 4, 0], QueueIo::new());
+        assert_eq!(computer.run(), ExecutionState::Halted(1101));
+
+        let mut computer = Computer::load_program(vec![1102i32, i32::MAX, 2, 0, 99], QueueIo::new());
+        assert!(matches!(computer.run(), ExecutionState::Faulted { fault: Fault::ArithmeticOverflow, .. }));
+    }
+
+    #[test]
+    pub fn i128_computer_handles_large_products() {
+        let mut computer = Computer::load_program(big_product_program::<i128>(), QueueIo::new());
+        assert_eq!(computer.run(), ExecutionState::Halted(1102));
+        assert_eq!(computer.io_system.output(), &[1 << 80]);
+
+        let mut computer = Computer::load_program(big_product_program::<i64>(), QueueIo::new());
+        assert!(matches!(computer.run(), ExecutionState::Faulted { fault: Fault::ArithmeticOverflow, .. }));
+    }
+
+    #[test]
+    pub fn addresses_that_do_not_fit_in_an_i64_fault() {
+        let program = vec![4i128, 1 << 70, 99];
+        let mut computer = Computer::load_program(program, QueueIo::new());
+        assert!(matches!(computer.run(), ExecutionState::Faulted { fault: Fault::AddressLimitExceeded(_), .. }));
+    }
+
+    #[cfg(feature = "bigint")]
+    #[test]
+    pub fn bigint_computer_never_overflows() {
+        use num_bigint::BigInt;
+
+        let mut computer = Computer::load_program(big_product_program::<BigInt>(), QueueIo::new());
+        computer.run();
+        let expected: BigInt = BigInt::from(1) << 80;
+        assert_eq!(computer.io_system.output(), std::slice::from_ref(&expected));
+
+        // Square it again, well past what an i128 could hold.
+        let mut program = big_product_program::<BigInt>();
+        program[1] = expected.clone();
+        program[2] = expected.clone();
+        let mut computer = Computer::load_program(program, QueueIo::new());
+        computer.run();
+        assert_eq!(computer.io_system.output(), &[&expected * &expected]);
+    }
+}
@@ -131,7 +131,7 @@ fn plot_wire(board: &mut Board, wire_input: Vec<Instruction>, wire: Wire) -> (i3
 
 fn parse_input(input: &str) -> Vec<Instruction> {
     input.split(',')
-        .map(|s| parse_instruction(s))
+        .map(parse_instruction)
         .collect()
 }
 
@@ -1,4 +1,5 @@
 
+#[allow(dead_code)]
 struct ConstrainedRange {
     min: u32,
     max: u32,
@@ -6,11 +7,12 @@ struct ConstrainedRange {
     max_digits: Vec<u8>,
 }
 
+#[allow(dead_code)]
 impl ConstrainedRange {
     fn new(min: u32, max: u32) -> Self {
         Self {
-            min: min,
-            max: max,
+            min,
+            max,
             digits: Self::num_to_digits(min),
             max_digits: Self::num_to_digits(max),
         }
@@ -33,7 +35,7 @@ impl ConstrainedRange {
         result
     }
 
-    fn digits_to_num(digits: &[u8]) -> u32 {
+    fn digits_to_num(_digits: &[u8]) -> u32 {
         0
     }
 
@@ -93,8 +95,8 @@ impl ConstrainedRange {
         let mut this_run_length = 1;
         let mut this_run_digit = digits[0];
 
-        for i in 1..digits.len() {
-            if digits[i] == this_run_digit {
+        for &digit in &digits[1..] {
+            if digit == this_run_digit {
                 this_run_length += 1;
             } else {
                 // Run is at an end, was it of length 2?
@@ -102,7 +104,7 @@ impl ConstrainedRange {
                     return true;
                 } else {
                     this_run_length = 1;
-                    this_run_digit = digits[i];
+                    this_run_digit = digit;
                 }
             }
         }
@@ -130,7 +132,7 @@ impl Iterator for ConstrainedRange {
             }
         }
 
-        return None;
+        None
     }
 }
 
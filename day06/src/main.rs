@@ -30,7 +30,7 @@ struct Body {
 impl Body {
     fn new(name: String) -> Self {
         Body {
-            name: name,
+            name,
             orbits: "".to_string(),
             orbitted_by: vec![],
             depth: Cell::new(0),
@@ -67,7 +67,7 @@ impl OrbitGraph {
     }
 
     /// Hmm, entry requires a moved, not borrowed, key.
-    fn entry(&mut self, key: String) -> Entry<String, Body> {
+    fn entry(&mut self, key: String) -> Entry<'_, String, Body> {
         self.0.entry(key)
     }
 
@@ -78,11 +78,11 @@ impl OrbitGraph {
     /// Calculates the depth of each node. We use interior mutability
     /// to remember these for part 2.
     fn calc_node_depth(&self, current_depth: usize, current_node: &str) {
-        let mut current_node = self.0.get(current_node).unwrap();
+        let current_node = self.0.get(current_node).unwrap();
         current_node.depth.set(current_depth);
 
         for child in &current_node.orbitted_by {
-            self.calc_node_depth(current_depth + 1, &child);
+            self.calc_node_depth(current_depth + 1, child);
         }
     }
 
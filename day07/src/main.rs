@@ -1,5 +1,5 @@
 use permutohedron::LexicalPermutation;
-use computer::{Computer, ComputerIo, ExecutionState};
+use computer::{Network, Program};
 
 fn get_phase_setting_permutations(mut phase_settings: Vec<i64>) -> Vec<Vec<i64>> {
     let mut permutations = Vec::new();
@@ -13,15 +13,9 @@ fn get_phase_setting_permutations(mut phase_settings: Vec<i64>) -> Vec<Vec<i64>>
     permutations
 }
 
-fn get_input(raw_input: &str) -> Vec<i64> {
-    raw_input.split(',')
-        .map(|s| s.parse::<i64>().expect("Input should be an integer"))
-        .collect()
-}
-
 fn get_puzzle_input() -> Vec<i64> {
     let data = include_str!("input.txt");
-    get_input(data)
+    Program::parse(data).expect("Puzzle input should be a valid program").into_words()
 }
 
 fn main() {
@@ -62,100 +56,40 @@ fn main() {
 
 
 fn calculate_output_signal_with_feedback(program: Vec<i64>, permutation: &[i64]) -> i64 {
-    let mut amp_a = make_amplifier(program.clone(), permutation[0], 0);
-    let mut amp_b = make_amplifier(program.clone(), permutation[1], 0);
-    let mut amp_c = make_amplifier(program.clone(), permutation[2], 0);
-    let mut amp_d = make_amplifier(program.clone(), permutation[3], 0);
-    let mut amp_e = make_amplifier(program.clone(), permutation[4], 0);
-
-    loop {
-        amp_a.run();
-
-        amp_b.io_system.value = amp_a.io_system.value.take();
-        amp_b.run();
-
-        amp_c.io_system.value = amp_b.io_system.value.take();
-        amp_c.run();
-
-        amp_d.io_system.value = amp_c.io_system.value.take();
-        amp_d.run();
-
-        amp_e.io_system.value = amp_d.io_system.value.take();
-        amp_e.run();
-
-        if let ExecutionState::Halted(_) = amp_e.execution_state {
-            return amp_e.io_system.value.unwrap();
-        } else {
-            amp_a.io_system.value = amp_e.io_system.value.take();
-        }
-    }
+    run_amplifiers(program, permutation, true)
 }
 
 fn calculate_output_signal(program: Vec<i64>, permutation: &[i64]) -> i64 {
-    let mut amp_a = make_amplifier(program.clone(), permutation[0], 0);
-    amp_a.run();
-    let stage_output = amp_a.io_system.value.unwrap();
-
-    let mut amp_b = make_amplifier(program.clone(), permutation[1], stage_output);
-    amp_b.run();
-    let stage_output = amp_b.io_system.value.unwrap();
-
-    let mut amp_c = make_amplifier(program.clone(), permutation[2], stage_output);
-    amp_c.run();
-    let stage_output = amp_c.io_system.value.unwrap();
-
-    let mut amp_d = make_amplifier(program.clone(), permutation[3], stage_output);
-    amp_d.run();
-    let stage_output = amp_d.io_system.value.unwrap();
-
-    let mut amp_e = make_amplifier(program.clone(), permutation[4], stage_output);
-    amp_e.run();
-    let stage_output = amp_e.io_system.value.unwrap();
-
-    stage_output
-}
-
-fn make_amplifier(
-    program: Vec<i64>,
-    phase_setting: i64,
-    value: i64) -> Computer<AutoComputerIoSystem>
-{
-    let io  = AutoComputerIoSystem::new(phase_setting, value);
-    Computer::load_program(program, io)
+    run_amplifiers(program, permutation, false)
 }
 
-pub struct AutoComputerIoSystem {
-    num_reads: i32,
-    phase_setting: i64,
-    // This is both the input and the output value.
-    // A bit nasty, but works for this problem.
-    value: Option<i64>,
-}
-
-impl ComputerIo for AutoComputerIoSystem {
-    fn try_read(&mut self, _message: &str) -> Option<i64> {
-        if self.num_reads == 0 {
-            self.num_reads += 1;
-            Some(self.phase_setting)
-        } else {
-            self.num_reads += 1;
-            self.value.take()
-        }
+/// Wires up a chain of amplifiers, one per phase setting, optionally with
+/// the last one feeding back into the first, and returns the final output
+/// signal from the last amplifier.
+fn run_amplifiers(program: Vec<i64>, permutation: &[i64], feedback: bool) -> i64 {
+    let mut network = Network::new();
+    let amps = permutation.iter()
+        .map(|&phase_setting| {
+            let amp = network.add_node(program.clone());
+            network.push_input(amp, phase_setting);
+            amp
+        })
+        .collect::<Vec<_>>();
+
+    for pair in amps.windows(2) {
+        network.connect(pair[0], pair[1]);
     }
 
-    fn write(&mut self, value: i64) {
-        self.value = Some(value);
+    let first = amps[0];
+    let last = amps[amps.len() - 1];
+    if feedback {
+        network.connect(last, first);
     }
-}
+    network.add_sink(last);
+    network.push_input(first, 0);
 
-impl AutoComputerIoSystem {
-    fn new(phase_setting: i64, value: i64) -> Self {
-        Self {
-            num_reads: 0,
-            phase_setting,
-            value: Some(value),
-        }
-    }
+    let outputs = network.run().expect("Amplifiers should run to completion");
+    *outputs[0].last().expect("Amplifier should produce an output signal")
 }
 
 #[cfg(test)]
@@ -166,8 +100,8 @@ mod tests {
     pub fn test1() {
         let permutations = get_phase_setting_permutations(vec![5, 6, 7, 8, 9]);
 
-        let program = get_input("3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,\
-            27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5");
+        let program = Program::parse("3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,\
+            27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5").unwrap().into_words();
 
         let mut max_output_signal = 0;
         for permutation in permutations {
@@ -52,9 +52,9 @@ fn main() {
     let mut final_image = Vec::<u32>::new();
 
     for pixel_index in 0..IMAGE_WIDTH * IMAGE_HEIGHT {
-        for layer_index in 0..layers.len() {
-            if layers[layer_index][pixel_index] != 2 {
-                final_image.push(layers[layer_index][pixel_index]);
+        for layer in &layers {
+            if layer[pixel_index] != 2 {
+                final_image.push(layer[pixel_index]);
                 break;
             }
         }
@@ -0,0 +1,14 @@
+use std::{env, fs, path::Path};
+use computer::{transpile, Image, Program};
+
+/// Translates the BOOST program into Rust, so that it can be run natively.
+fn main() {
+    println!("cargo:rerun-if-changed=src/input.txt");
+
+    let source = fs::read_to_string("src/input.txt").expect("Cannot read src/input.txt");
+    let program = Program::parse(&source).expect("Puzzle input should be a valid program");
+    let rust = transpile(&Image::from(program), "run_boost");
+
+    let out_dir = env::var("OUT_DIR").expect("OUT_DIR is not set");
+    fs::write(Path::new(&out_dir).join("boost.rs"), rust).expect("Cannot write boost.rs");
+}
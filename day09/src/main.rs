@@ -1,39 +1,80 @@
-use computer::{Computer, ComputerIo, StandardComputerIoSystem, ExecutionState};
+use computer::StandardComputerIoSystem;
 
-fn main() {
-    let program = get_puzzle_input();
-    let io  = StandardComputerIoSystem::new();
-    let mut computer = Computer::load_program(program, io);
-    computer.run();
-}
-
-fn get_input(raw_input: &str) -> Vec<i64> {
-    raw_input.split(',')
-        .map(|s| s.parse::<i64>().expect("Input should be an integer"))
-        .collect()
+/// The puzzle input translated to Rust by the build script.
+mod boost {
+    include!(concat!(env!("OUT_DIR"), "/boost.rs"));
 }
 
-fn get_puzzle_input() -> Vec<i64> {
-    let data = include_str!("input.txt");
-    get_input(data)
+fn main() {
+    let io = StandardComputerIoSystem::new();
+    boost::run_boost(io);
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use computer::{Computer, Program, QueueIo};
+
+    fn get_puzzle_input() -> Vec<i64> {
+        let data = include_str!("input.txt");
+        Program::parse(data).expect("Puzzle input should be a valid program").into_words()
+    }
+
+    fn run_program(program: Vec<i64>, inputs: Vec<i64>) -> Vec<i64> {
+        let mut io = QueueIo::new();
+        io.extend_input(inputs);
+        let mut computer = Computer::load_program(program, io);
+        computer.run();
+        computer.io_system.drain_output().collect()
+    }
 
     #[test]
     pub fn test1() {
-        let input = get_input("109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99");
+        let input = Program::parse("109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99").unwrap().into_words();
+        let output = run_program(input.clone(), vec![]);
+        assert_eq!(output, input, "Program should output a copy of itself");
     }
 
     #[test]
     pub fn test2() {
-        let input = get_input("1102,34915192,34915192,7,4,7,99,0");
+        let input = Program::parse("1102,34915192,34915192,7,4,7,99,0").unwrap().into_words();
+        let output = run_program(input, vec![]);
+        assert_eq!(output.len(), 1);
+        assert_eq!(output[0].to_string().len(), 16, "Output should be a 16 digit number");
     }
 
     #[test]
     pub fn test3() {
-        let input = get_input("104,1125899906842624,99");
+        let input = Program::parse("104,1125899906842624,99").unwrap().into_words();
+        let output = run_program(input, vec![]);
+        assert_eq!(output, vec![1125899906842624]);
+    }
+
+    #[test]
+    pub fn boost_test_mode() {
+        // Any extra outputs are opcodes that are not working.
+        let output = run_program(get_puzzle_input(), vec![1]);
+        assert_eq!(output, vec![4006117640]);
+    }
+
+    #[test]
+    pub fn boost_sensor_boost_mode() {
+        let output = run_program(get_puzzle_input(), vec![2]);
+        assert_eq!(output, vec![88231]);
+    }
+
+    #[test]
+    pub fn native_boost_matches_the_interpreter() {
+        for &mode in &[1, 2] {
+            let mut io = QueueIo::new();
+            io.extend_input(vec![mode]);
+            let mut interpreted = Computer::load_program(get_puzzle_input(), io.clone());
+            interpreted.run();
+
+            let mut native = boost::run_boost(io);
+            assert_eq!(native.execution_state, interpreted.execution_state);
+            assert_eq!(native.io_system.drain_output().collect::<Vec<_>>(),
+                       interpreted.io_system.drain_output().collect::<Vec<_>>());
+        }
     }
 }
@@ -0,0 +1,485 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, BufRead, Write};
+use computer::{disassemble_from, Computer, ControlFlowGraph, ExecutionState, Image, Line, Program, QueueIo};
+
+/// The number of instructions that can be undone with `back` and `rewind`.
+const HISTORY_CAPACITY: usize = 1_000_000;
+
+/// The number of lines that `list` shows before the IP.
+const LINES_BEFORE_IP: usize = 3;
+
+/// The most words that an instruction can take up.
+const MAX_INSTRUCTION_LEN: usize = 4;
+
+/// The most memory cells that `mem` will show at once.
+const MAX_MEM_COUNT: usize = 4096;
+
+const HELP: &str = "\
+Commands:
+  s, step [n]          Execute n instructions (default 1)
+  c, continue [n]      Run until a breakpoint, watchpoint, halt, fault or input starvation,
+                       or until n instructions have been executed
+  back [n]             Undo the last n instructions (default 1)
+  rewind <addr>        Undo instructions until the IP is back at addr
+  b, break <addr>      Set a breakpoint on an address
+  d, delete <addr>     Remove a breakpoint
+  w, watch <addr>      Stop when the value at an address changes
+  unwatch <addr>       Remove a watchpoint
+  r, regs              Show the registers and execution state
+  m, mem <addr> [n]    Dump n memory cells (default 8, at most 4096) starting at addr
+  l, list [n]          Disassemble a few lines before the IP and n (default 5) from it
+  cfg                  Print the control flow graph of memory, from the IP, as Graphviz DOT
+  i, input <v>...      Queue values for the program to read
+  h, help              Show this help
+  q, quit              Exit the debugger";
+
+fn main() {
+    let path = match std::env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("Usage: debugger <program-file>");
+            std::process::exit(1);
+        }
+    };
+
+    let program = match Program::from_file(&path) {
+        Ok(program) => program.into_words(),
+        Err(err) => {
+            eprintln!("Cannot load {}: {}", path, err);
+            std::process::exit(1);
+        }
+    };
+
+    let mut debugger = Debugger::new(program);
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    debugger.run_session(stdin.lock(), &mut stdout.lock(), true).expect("Failed to write to stdout");
+}
+
+/// Why the debugger stopped executing the program.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum StopReason {
+    /// The requested number of steps were executed.
+    Stepped,
+    Breakpoint(usize),
+    Watchpoint { address: usize, old: i64, new: i64 },
+    /// The computer is no longer running (halted, faulted or waiting on input).
+    State(ExecutionState),
+}
+
+struct Debugger {
+    computer: Computer<QueueIo>,
+    breakpoints: BTreeSet<usize>,
+    /// Watched addresses and the value they had when last checked.
+    watchpoints: BTreeMap<usize, i64>,
+}
+
+impl Debugger {
+    fn new(program: Vec<i64>) -> Self {
+        let mut computer = Computer::load_program(program, QueueIo::new());
+        computer.enable_history(HISTORY_CAPACITY);
+
+        Self {
+            computer,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeMap::new(),
+        }
+    }
+
+    /// Reads commands until end of input or `quit`.
+    fn run_session<R, W>(&mut self, mut input: R, output: &mut W, prompt: bool) -> io::Result<()>
+        where R: BufRead,
+              W: Write
+    {
+        if prompt {
+            writeln!(output, "Intcode debugger. Type 'help' for a list of commands.")?;
+        }
+
+        loop {
+            if prompt {
+                write!(output, "(icdb) ")?;
+                output.flush()?;
+            }
+
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 || !self.execute(&line, output)? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Executes one command. Returns false if the debugger should exit.
+    fn execute<W: Write>(&mut self, line: &str, output: &mut W) -> io::Result<bool> {
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some(command) => command,
+            None => return Ok(true),
+        };
+        let args = words.collect::<Vec<_>>();
+
+        match (command, parse_args(&args)) {
+            (_, Err(bad)) => writeln!(output, "Not a number: {}", bad)?,
+            ("s", Ok(args)) | ("step", Ok(args)) => {
+                let count = args.first().copied().unwrap_or(1).max(1) as usize;
+                let reason = self.resume(Some(count));
+                self.report(reason, output)?;
+            },
+            ("c", Ok(args)) | ("continue", Ok(args)) => {
+                let reason = match args.first() {
+                    Some(&budget) => self.continue_with_budget(budget.max(0) as usize),
+                    None => self.resume(None),
+                };
+                self.report(reason, output)?;
+            },
+            ("back", Ok(args)) => {
+                let count = args.first().copied().unwrap_or(1).max(1);
+                let undone = (0..count).take_while(|_| self.computer.step_back()).count();
+                if (undone as i64) < count {
+                    writeln!(output, "Only {} instructions could be undone", undone)?;
+                }
+                self.refresh_watchpoints();
+                self.print_listing(1, output)?;
+            },
+            ("rewind", Ok(args)) => {
+                match addresses(&args).next() {
+                    Some(address) => {
+                        if !self.computer.run_back_to(address) {
+                            writeln!(output, "Ran out of history before reaching {}", address)?;
+                        }
+                        self.refresh_watchpoints();
+                        self.print_listing(1, output)?;
+                    },
+                    None => writeln!(output, "Usage: rewind <addr>")?,
+                }
+            },
+            ("b", Ok(args)) | ("break", Ok(args)) => {
+                for address in addresses(&args) {
+                    self.breakpoints.insert(address);
+                    writeln!(output, "Breakpoint set at {}", address)?;
+                }
+            },
+            ("d", Ok(args)) | ("delete", Ok(args)) => {
+                for address in addresses(&args) {
+                    self.breakpoints.remove(&address);
+                }
+            },
+            ("w", Ok(args)) | ("watch", Ok(args)) => {
+                for address in addresses(&args) {
+                    let value = self.read_memory(address);
+                    self.watchpoints.insert(address, value);
+                    writeln!(output, "Watching [{}] = {}", address, value)?;
+                }
+            },
+            ("unwatch", Ok(args)) => {
+                for address in addresses(&args) {
+                    self.watchpoints.remove(&address);
+                }
+            },
+            ("r", Ok(_)) | ("regs", Ok(_)) => self.print_registers(output)?,
+            ("m", Ok(args)) | ("mem", Ok(args)) => {
+                let start = args.first().copied().unwrap_or(0).max(0) as usize;
+                let count = args.get(1).copied().unwrap_or(8).max(0) as usize;
+                match start.checked_add(count) {
+                    Some(_) if count > MAX_MEM_COUNT => writeln!(output, "Can show at most {} memory cells", MAX_MEM_COUNT)?,
+                    Some(end) => {
+                        for address in start..end {
+                            writeln!(output, "{:>6}: {}", address, self.read_memory(address))?;
+                        }
+                    },
+                    None => writeln!(output, "Address range is too large")?,
+                }
+            },
+            ("l", Ok(args)) | ("list", Ok(args)) => {
+                let count = args.first().copied().unwrap_or(5).max(0) as usize;
+                self.print_lines_before_ip(LINES_BEFORE_IP, output)?;
+                self.print_listing(count, output)?;
+            },
+            ("cfg", Ok(_)) => self.print_control_flow_graph(output)?,
+            ("i", Ok(args)) | ("input", Ok(args)) => {
+                for value in args {
+                    self.computer.provide_input(value);
+                }
+            },
+            ("h", Ok(_)) | ("help", Ok(_)) => writeln!(output, "{}", HELP)?,
+            ("q", Ok(_)) | ("quit", Ok(_)) => return Ok(false),
+            (unknown, Ok(_)) => writeln!(output, "Unknown command '{}'. Type 'help' for a list of commands.", unknown)?,
+        }
+
+        Ok(true)
+    }
+
+    /// Executes instructions until `steps` have been executed (or forever
+    /// if `None`), or something interesting happens. Outputs from the
+    /// program are collected in the IO system.
+    fn resume(&mut self, steps: Option<usize>) -> StopReason {
+        let mut executed = 0;
+
+        loop {
+            // Don't stop on a breakpoint at the IP we are starting from,
+            // otherwise we could never continue from one.
+            if executed > 0 && self.breakpoints.contains(&self.computer.instruction_pointer()) {
+                return StopReason::Breakpoint(self.computer.instruction_pointer());
+            }
+
+            if steps.is_some_and(|steps| executed >= steps) {
+                return StopReason::Stepped;
+            }
+
+            let state = self.computer.step();
+            executed += 1;
+
+            let changed_watchpoint = self.watchpoints.iter()
+                .map(|(&address, &old)| (address, old, self.read_memory(address)))
+                .find(|&(_, old, new)| old != new);
+            if let Some((address, old, new)) = changed_watchpoint {
+                self.watchpoints.insert(address, new);
+                return StopReason::Watchpoint { address, old, new };
+            }
+
+            if state != ExecutionState::Running {
+                return StopReason::State(state);
+            }
+        }
+    }
+
+    /// As `resume`, but gives up after `budget` instructions, leaving the
+    /// computer in the `BudgetExhausted` state, so that a program that is
+    /// stuck in a loop can still be stopped.
+    fn continue_with_budget(&mut self, budget: usize) -> StopReason {
+        if self.breakpoints.is_empty() && self.watchpoints.is_empty() {
+            // There is nothing to check between instructions.
+            return StopReason::State(self.computer.run_with_budget(budget));
+        }
+
+        match self.resume(Some(budget)) {
+            // A budget of 0 just moves the computer to the exhausted state.
+            StopReason::Stepped => StopReason::State(self.computer.run_with_budget(0)),
+            reason => reason,
+        }
+    }
+
+    fn report<W: Write>(&mut self, reason: StopReason, output: &mut W) -> io::Result<()> {
+        for value in self.computer.io_system.drain_output() {
+            writeln!(output, "Output: {}", value)?;
+        }
+
+        match reason {
+            StopReason::Stepped => {},
+            StopReason::Breakpoint(address) => writeln!(output, "Breakpoint at {}", address)?,
+            StopReason::Watchpoint { address, old, new } =>
+                writeln!(output, "Watchpoint [{}] changed from {} to {}", address, old, new)?,
+            StopReason::State(ExecutionState::WaitingOnInput) =>
+                writeln!(output, "Waiting on input. Use 'input' to provide some.")?,
+            StopReason::State(ExecutionState::BudgetExhausted) =>
+                writeln!(output, "Instruction budget used up. Use 'continue' to carry on.")?,
+            StopReason::State(state) => writeln!(output, "Stopped: {:?}", state)?,
+        }
+
+        self.print_listing(1, output)
+    }
+
+    fn print_registers<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        writeln!(output, "instruction_pointer = {}", self.computer.instruction_pointer())?;
+        writeln!(output, "relative_base = {}", self.computer.relative_base())?;
+        writeln!(output, "execution_state = {:?}", self.computer.execution_state)
+    }
+
+    fn print_listing<W: Write>(&self, count: usize, output: &mut W) -> io::Result<()> {
+        let memory = self.computer.memory().dense();
+        for line in disassemble_from(memory, self.computer.instruction_pointer(), count) {
+            self.print_line(&line, output)?;
+        }
+
+        Ok(())
+    }
+
+    /// Prints up to `count` lines that end just before the IP. Code can't
+    /// be decoded backwards, so we decode forwards from the earliest address
+    /// that lines up with the IP, which is almost always where the code
+    /// before it really starts.
+    fn print_lines_before_ip<W: Write>(&self, count: usize, output: &mut W) -> io::Result<()> {
+        let memory = self.computer.memory().dense();
+        let ip = self.computer.instruction_pointer().min(memory.len());
+        let earliest = ip.saturating_sub(count * MAX_INSTRUCTION_LEN);
+
+        for start in earliest..ip {
+            let lines = disassemble_from(memory, start, usize::MAX)
+                .into_iter()
+                .take_while(|line| line.address() < ip)
+                .collect::<Vec<_>>();
+            let lines_up = lines.last().is_some_and(|line| line.address() + line.word_count() == ip);
+            if lines_up {
+                for line in &lines[lines.len().saturating_sub(count)..] {
+                    self.print_line(line, output)?;
+                }
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn print_line<W: Write>(&self, line: &Line, output: &mut W) -> io::Result<()> {
+        let marker = if line.address() == self.computer.instruction_pointer() { "=>" } else { "  " };
+        let breakpoint = if self.breakpoints.contains(&line.address()) { "*" } else { " " };
+        writeln!(output, "{}{}{}", marker, breakpoint, line)
+    }
+
+    /// Prints the graph of the code as it is now, so code the program has
+    /// patched in shows up, starting from the IP.
+    fn print_control_flow_graph<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        let mut image = Image::from(self.computer.memory().dense().to_vec());
+        image.entry_point = Some(self.computer.instruction_pointer());
+        write!(output, "{}", ControlFlowGraph::new(&image).to_dot())
+    }
+
+    /// Updates the watched values after memory has been changed by undoing
+    /// instructions, so that the watchpoints don't fire straight away.
+    fn refresh_watchpoints(&mut self) {
+        let addresses = self.watchpoints.keys().copied().collect::<Vec<_>>();
+        for address in addresses {
+            let value = self.read_memory(address);
+            self.watchpoints.insert(address, value);
+        }
+    }
+
+    fn read_memory(&self, address: usize) -> i64 {
+        self.computer.memory().get(address)
+    }
+}
+
+/// Parses all the arguments to a command as numbers, or returns the
+/// first one that is not a number.
+fn parse_args<'a>(args: &[&'a str]) -> Result<Vec<i64>, &'a str> {
+    args.iter()
+        .map(|&arg| arg.parse::<i64>().map_err(|_| arg))
+        .collect()
+}
+
+fn addresses(args: &[i64]) -> impl Iterator<Item = usize> + '_ {
+    args.iter().filter(|&&arg| arg >= 0).map(|&arg| arg as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs a script of debugger commands and returns everything printed.
+    fn run_script(program: Vec<i64>, script: &str) -> String {
+        let mut debugger = Debugger::new(program);
+        let mut output = Vec::new();
+        debugger.run_session(script.as_bytes(), &mut output, false).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    /// Reads a number into address 9, then outputs it twice.
+    fn echo_program() -> Vec<i64> {
+        vec![3, 9, 4, 9, 4, 9, 99, 0, 0, 0]
+    }
+
+    #[test]
+    pub fn step_and_registers() {
+        let output = run_script(echo_program(), "input 7\nstep\nregs\nstep 2\n");
+        assert!(output.contains("instruction_pointer = 2"));
+        assert!(output.contains("relative_base = 0"));
+        assert!(output.contains("Output: 7\n"));
+        assert!(output.contains("=>      6: HLT"));
+    }
+
+    #[test]
+    pub fn continue_stops_at_breakpoint() {
+        let output = run_script(echo_program(), "input 5\nbreak 4\ncontinue\ncontinue\n");
+        assert!(output.contains("Breakpoint set at 4"));
+        assert!(output.contains("Output: 5\nBreakpoint at 4\n=>*     4: OUT [9]"));
+        assert!(output.ends_with("Stopped: Halted(3)\n=>      6: HLT\n"));
+    }
+
+    #[test]
+    pub fn continue_stops_at_watchpoint() {
+        let output = run_script(echo_program(), "watch 9\ninput 42\nc\n");
+        assert!(output.contains("Watching [9] = 0"));
+        assert!(output.contains("Watchpoint [9] changed from 0 to 42"));
+    }
+
+    #[test]
+    pub fn continue_reports_input_starvation() {
+        let output = run_script(echo_program(), "c\nmem 8 2\n");
+        assert!(output.contains("Waiting on input."));
+        assert!(output.contains("     8: 0\n     9: 0\n"));
+    }
+
+    #[test]
+    pub fn back_and_rewind_undo_instructions() {
+        let output = run_script(echo_program(), "input 3\nstep 3\nback\nregs\nrewind 0\nmem 9 1\nback\n");
+        assert!(output.contains("=>      4: OUT [9]"));
+        assert!(output.contains("instruction_pointer = 4"));
+        assert!(output.contains("=>      0: IN [9]"));
+        assert!(output.contains("     9: 0\n"));
+        assert!(output.contains("Only 0 instructions could be undone"));
+    }
+
+    #[test]
+    pub fn list_shows_lines_around_the_ip() {
+        let output = run_script(echo_program(), "input 7\nstep 2\nlist 2\n");
+        assert!(output.ends_with(concat!(
+            "        0: IN [9]\n",
+            "        2: OUT [9]\n",
+            "=>      4: OUT [9]\n",
+            "        6: HLT\n",
+        )));
+
+        // At the start there is nothing before the IP.
+        let output = run_script(echo_program(), "list 1\n");
+        assert_eq!(output, "=>      0: IN [9]\n");
+    }
+
+    #[test]
+    pub fn mem_rejects_addresses_that_are_too_large() {
+        let output = run_script(echo_program(), "mem 9 2\nmem 18446744073709551615 8\n");
+        assert_eq!(output, "     9: 0\n    10: 0\nNot a number: 18446744073709551615\n");
+
+        let output = run_script(echo_program(), "mem 0 9223372036854775807\nmem 0 4097\n");
+        assert_eq!(output, "Can show at most 4096 memory cells\nCan show at most 4096 memory cells\n");
+    }
+
+    #[test]
+    pub fn continue_can_be_given_an_instruction_budget() {
+        // Loops forever, counting in address 7.
+        let program = vec![101, 1, 7, 7, 1105, 1, 0, 0];
+        let output = run_script(program.clone(), "continue 10\nmem 7 1\nregs\n");
+        assert!(output.starts_with("Instruction budget used up. Use 'continue' to carry on.\n=>      0: ADD #1, [7], [7]\n"));
+        assert!(output.contains("     7: 5\n"));
+        assert!(output.ends_with("execution_state = BudgetExhausted\n"));
+
+        // With a watchpoint set, it still stops when the budget runs out.
+        let output = run_script(program.clone(), "watch 0\nc 10\nc 4\nm 7 1\n");
+        assert!(output.contains("Instruction budget used up. Use 'continue' to carry on.\n=>      0: ADD #1, [7], [7]\n"));
+        assert!(output.ends_with("     7: 7\n"));
+
+        // A breakpoint that is reached first still wins.
+        let output = run_script(program, "break 4\nc 10\n");
+        assert!(output.contains("Breakpoint at 4\n"));
+    }
+
+    #[test]
+    pub fn cfg_follows_patched_code() {
+        // Writes a Halt over the word at 7, which is not an instruction at
+        // first, then jumps to it.
+        let program = vec![1101, 99, 0, 7, 1105, 1, 7, 0];
+        let output = run_script(program, "cfg\nstep\ncfg\n");
+        let graphs = output.split("digraph").collect::<Vec<_>>();
+        assert_eq!(graphs.len(), 3);
+        assert!(graphs[1].contains("    b0 -> \"invalid7\" [color=red];\n"));
+        assert!(graphs[2].contains("    entry -> b4;\n"));
+        assert!(graphs[2].contains("    b4 -> b7 [label=\"true\"];\n"));
+        assert!(graphs[2].contains("7: HLT"));
+    }
+
+    #[test]
+    pub fn bad_commands_are_reported() {
+        let output = run_script(echo_program(), "frob\nbreak x\nquit\nstep\n");
+        assert!(output.contains("Unknown command 'frob'"));
+        assert!(output.contains("Not a number: x"));
+        assert!(!output.contains("=>"), "Nothing should run after quit");
+    }
+}
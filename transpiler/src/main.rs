@@ -0,0 +1,82 @@
+use std::fs;
+use computer::{transpile, Image, Program};
+
+/// Translates an Intcode program, either as text or as a program image, into
+/// Rust source and prints it. See `computer::transpile` for what the
+/// generated function does.
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let path = match args.next() {
+        Some(path) => path,
+        None => {
+            eprintln!("Usage: transpiler <program-file> [function-name]");
+            std::process::exit(1);
+        }
+    };
+    let function_name = args.next().unwrap_or_else(|| "run_program".to_string());
+    if !is_identifier(&function_name) {
+        eprintln!("Usage: transpiler <program-file> [function-name]");
+        eprintln!("The function name must be letters, digits and underscores, not start with a digit, and not be `_` or a Rust keyword");
+        std::process::exit(1);
+    }
+
+    let image = match load(&path) {
+        Ok(image) => image,
+        Err(err) => {
+            eprintln!("Cannot load {}: {}", path, err);
+            std::process::exit(1);
+        }
+    };
+
+    print!("{}", transpile(&image, &function_name));
+}
+
+/// Loads a program image, or a program in the usual comma separated text.
+fn load(path: &str) -> std::io::Result<Image> {
+    let bytes = fs::read(path)?;
+    if bytes.starts_with(b"ICIM") {
+        Image::read_from(&mut bytes.as_slice())
+    } else {
+        Program::from_reader(bytes.as_slice()).map(Image::from)
+    }
+}
+
+/// The strict and reserved keywords of every Rust edition, none of which can
+/// name a function.
+const KEYWORDS: &[&str] = &[
+    "as", "break", "const", "continue", "crate", "else", "enum", "extern", "false", "fn", "for", "if", "impl",
+    "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return", "self", "Self", "static",
+    "struct", "super", "trait", "true", "type", "unsafe", "use", "where", "while", "async", "await", "dyn",
+    "abstract", "become", "box", "do", "final", "gen", "macro", "override", "priv", "try", "typeof",
+    "unsized", "virtual", "yield",
+];
+
+/// Returns whether `name` can be used as the name of the generated function.
+fn is_identifier(name: &str) -> bool {
+    if name == "_" || KEYWORDS.contains(&name) {
+        return false;
+    }
+
+    match name.chars().next() {
+        Some(first) if !first.is_ascii_digit() => name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn function_names_must_be_identifiers() {
+        assert!(is_identifier("run_program"));
+        assert!(is_identifier("_run2"));
+        assert!(!is_identifier(""));
+        assert!(!is_identifier("2run"));
+        assert!(!is_identifier("run-program"));
+        assert!(!is_identifier("_"));
+        assert!(!is_identifier("fn"));
+        assert!(!is_identifier("self"));
+        assert!(!is_identifier("loop"));
+    }
+}